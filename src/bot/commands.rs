@@ -1,4 +1,6 @@
 use teloxide::utils::command::BotCommands;
+use crate::core::safety::Rule;
+use crate::storage::Role;
 
 #[derive(BotCommands, Clone)]
 #[command(rename_rule = "lowercase", description = "Kora Reclaim Bot Commands")]
@@ -11,6 +13,14 @@ pub enum Command {
     Sweep,
     #[command(description = "Show recent event history")]
     Log,
+    #[command(description = "Show the last scan report with skip reasons")]
+    Report,
+    #[command(description = "Scan now and show what a sweep would close, without closing anything")]
+    Preview,
+    #[command(description = "Pause scheduled sweeps: /pause [duration, e.g. 30m, 2h, 1d]")]
+    Pause(String),
+    #[command(description = "Resume scheduled sweeps")]
+    Resume,
     #[command(description = "Switch between Demo and Real modes")]
     Mode,
     #[command(description = "Show help information")]
@@ -19,4 +29,74 @@ pub enum Command {
     Host,
     #[command(description = "Check system health and connectivity")]
     Health,
+    #[command(description = "List users and their roles")]
+    Users,
+    #[command(description = "Add a user or change their role: /grant <user_id> <viewer|operator|owner>")]
+    Grant(String),
+    #[command(description = "Remove a user: /revoke <user_id>")]
+    Revoke(String),
+    #[command(description = "Protect accounts from sweeps: /whitelist add|remove [mint] <pubkey>, /whitelist list")]
+    Whitelist(String),
+}
+
+impl Command {
+    /// The least role allowed to run this command.
+    pub fn required_role(&self) -> Role {
+        match self {
+            Command::Start
+            | Command::Stats
+            | Command::Log
+            | Command::Report
+            | Command::Help
+            | Command::Host
+            | Command::Health => Role::Viewer,
+            Command::Whitelist(args) => match WhitelistAction::parse(args) {
+                Ok(WhitelistAction::List) => Role::Viewer,
+                _ => Role::Owner,
+            },
+            Command::Sweep | Command::Preview | Command::Pause(_) | Command::Resume => Role::Operator,
+            Command::Mode | Command::Users | Command::Grant(_) | Command::Revoke(_) => Role::Owner,
+        }
+    }
+
+    /// Whether users who are not registered may run this in Demo mode. Only
+    /// commands that show nothing about the operator's accounts and change
+    /// nothing that outlasts Demo mode qualify; a demo `/sweep` is simulated.
+    /// `/stats` is not one: it reports lifetime totals from the ledger.
+    pub fn open_in_demo(&self) -> bool {
+        matches!(
+            self,
+            Command::Start | Command::Help | Command::Host | Command::Health | Command::Sweep
+        )
+    }
+}
+
+/// A parsed `/whitelist` command. Entries are stored as `account` and `mint`
+/// rules, the same ones `koralreef rules` manages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WhitelistAction {
+    Add(Rule),
+    Remove(Rule),
+    List,
+}
+
+impl WhitelistAction {
+    pub const USAGE: &'static str = "Usage: /whitelist add|remove [account|mint] <pubkey>, or /whitelist list";
+
+    /// Parses the text after `/whitelist`. A bare pubkey is a token account.
+    pub fn parse(args: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = args.split_whitespace().collect();
+        let rule = |kind: &str, value: &str| match kind {
+            "account" | "mint" => Rule::parse(kind, value),
+            _ => anyhow::bail!("{}", Self::USAGE),
+        };
+        Ok(match parts.as_slice() {
+            [] | ["list"] => Self::List,
+            ["add", value] => Self::Add(rule("account", value)?),
+            ["add", kind, value] => Self::Add(rule(kind, value)?),
+            ["remove", value] => Self::Remove(rule("account", value)?),
+            ["remove", kind, value] => Self::Remove(rule(kind, value)?),
+            _ => anyhow::bail!("{}", Self::USAGE),
+        })
+    }
 }
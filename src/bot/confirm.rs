@@ -0,0 +1,96 @@
+//! One-time confirmations for commands that can move funds.
+//!
+//! A destructive command replies with Confirm and Cancel buttons carrying a
+//! random nonce. The nonce is only honoured once, only for the user who
+//! issued the command, and only until it expires.
+
+use std::collections::HashMap;
+use std::fmt;
+use rand::{thread_rng, RngCore};
+use crate::storage::Role;
+
+/// How long a confirmation stays valid, in seconds.
+pub const CONFIRM_TTL_SECS: i64 = 60;
+
+/// Callback data prefixes for the two buttons.
+pub const CONFIRM_PREFIX: &str = "confirm:";
+pub const CANCEL_PREFIX: &str = "cancel:";
+
+/// A command waiting for its confirmation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingAction {
+    /// A real-mode sweep.
+    Sweep,
+    /// Switching from Demo to Real mode.
+    EnableReal,
+}
+
+impl PendingAction {
+    /// The least role allowed to confirm this action, checked again on
+    /// confirmation in case the user lost access meanwhile.
+    pub fn required_role(&self) -> Role {
+        match self {
+            PendingAction::Sweep => Role::Operator,
+            PendingAction::EnableReal => Role::Owner,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmError {
+    /// Never issued, already used or cancelled.
+    Unknown,
+    Expired,
+    /// Someone other than the issuing user tapped the button.
+    WrongUser,
+}
+
+impl fmt::Display for ConfirmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfirmError::Unknown => write!(f, "This confirmation was already used or cancelled."),
+            ConfirmError::Expired => write!(f, "This confirmation expired; send the command again."),
+            ConfirmError::WrongUser => write!(f, "Only the user who sent the command can confirm it."),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    action: PendingAction,
+    user_id: u64,
+    expires_at: i64,
+}
+
+/// Outstanding confirmations, keyed by nonce.
+#[derive(Debug, Default)]
+pub struct Confirmations {
+    pending: HashMap<String, Pending>,
+}
+
+impl Confirmations {
+    /// Records `action` for `user_id` and returns its nonce. Expired entries
+    /// are dropped on the way.
+    pub fn issue(&mut self, action: PendingAction, user_id: u64, now: i64) -> String {
+        self.pending.retain(|_, pending| pending.expires_at > now);
+        let mut bytes = [0u8; 8];
+        thread_rng().fill_bytes(&mut bytes);
+        let nonce: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        self.pending.insert(nonce.clone(), Pending { action, user_id, expires_at: now + CONFIRM_TTL_SECS });
+        nonce
+    }
+
+    /// Consumes `nonce`. A tap by another user leaves it in place for the
+    /// issuing user.
+    pub fn take(&mut self, nonce: &str, user_id: u64, now: i64) -> Result<PendingAction, ConfirmError> {
+        let pending = self.pending.get(nonce).ok_or(ConfirmError::Unknown)?;
+        if pending.user_id != user_id {
+            return Err(ConfirmError::WrongUser);
+        }
+        let pending = self.pending.remove(nonce).ok_or(ConfirmError::Unknown)?;
+        if pending.expires_at <= now {
+            return Err(ConfirmError::Expired);
+        }
+        Ok(pending.action)
+    }
+}
@@ -1,23 +1,41 @@
 pub mod commands;
+pub mod confirm;
 
 use teloxide::prelude::*;
 use teloxide::utils::command::BotCommands;
 use crate::state::SharedState;
 use crate::config::Config;
-use crate::bot::commands::Command;
-use crate::storage::Storage;
-use log::info;
+use crate::bot::commands::{Command, WhitelistAction};
+use crate::core::safety::Rule;
+use crate::core::scanner::Scanner;
+use crate::core::sweep::preview_sweep;
+use solana_sdk::signer::Signer;
+use crate::bot::confirm::{ConfirmError, PendingAction, CANCEL_PREFIX, CONFIRM_PREFIX, CONFIRM_TTL_SECS};
+use crate::core::safety::unix_now;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile};
+use crate::storage::{parse_duration, Pause, Role, Storage};
+use log::{info, warn};
 use std::sync::Arc;
 
+/// Telegram rejects messages longer than 4096 characters; stay under it.
+const MAX_MESSAGE_LEN: usize = 4000;
+
+/// Accounts listed inline when a preview is sent as a file.
+const PREVIEW_TOP_ACCOUNTS: usize = 10;
+
+/// The help, host and health texts are written for Telegram's legacy Markdown.
+#[allow(deprecated)]
+const LEGACY_MARKDOWN: teloxide::types::ParseMode = teloxide::types::ParseMode::Markdown;
+
 pub async fn start_bot(config: Config, state: SharedState, storage: Arc<Storage>) {
     let bot = Bot::new(config.telegram.bot_token.clone());
 
     // Register commands in the Telegram UI menu
     let _ = bot.set_my_commands(Command::bot_commands()).await;
 
-    let handler = Update::filter_message()
-        .filter_command::<Command>()
-        .endpoint(handle_command);
+    let handler = dptree::entry()
+        .branch(Update::filter_message().filter_command::<Command>().endpoint(handle_command))
+        .branch(Update::filter_callback_query().endpoint(handle_callback));
 
     info!("Starting Telegram bot...");
     Dispatcher::builder(bot, handler)
@@ -43,29 +61,37 @@ async fn handle_command(
         s.mode
     };
 
-    if current_mode == crate::config::AppMode::Real {
-        // In Real mode, handle authorization strictly
-        let admin = storage.get_admin().unwrap_or(None);
-        let is_admin = match admin {
-            Some(id) => id == user_id,
-            None => {
-                info!("No admin found. Setting first messenger (user {}) as admin.", user_id);
-                storage.set_admin(user_id).unwrap();
-                bot.send_message(msg.chat.id, "🔐 You have been registered as the sole administrator for this koralreef worker.").await?;
-                true
-            }
-        };
-
-        if !is_admin {
-            bot.send_message(msg.chat.id, "🚫 Unauthorized. This bot is locked to another administrator.").await?;
+    let role = match storage.user_role(user_id) {
+        Ok(role) => role,
+        Err(e) => {
+            // Fail closed: a user table we cannot read grants nothing.
+            warn!("Failed to look up user {}: {}", user_id, e);
+            bot.send_message(msg.chat.id, "🚫 Unable to check your access right now.").await?;
             return Ok(());
         }
-    } else {
-        // In Demo mode, we still track the admin but don't block others
-        let admin = storage.get_admin().unwrap_or(None);
-        if admin.is_none() {
-            let _ = storage.set_admin(user_id);
+    };
+    let role = match role {
+        Some(role) => Some(role),
+        None if storage.claim_first_owner(user_id).unwrap_or(false) => {
+            info!("No users found. Registering first messenger (user {}) as owner.", user_id);
+            bot.send_message(msg.chat.id, "🔐 You have been registered as the owner of this koralreef worker.").await?;
+            Some(Role::Owner)
         }
+        // Demo mode only simulates sweeps, so strangers may try the demo.
+        None if current_mode == crate::config::AppMode::Demo && cmd.open_in_demo() => None,
+        None => {
+            bot.send_message(msg.chat.id, "🚫 Unauthorized. Ask an owner of this worker to /grant you access.").await?;
+            return Ok(());
+        }
+    };
+
+    let required = cmd.required_role();
+    if let Some(role) = role.filter(|role| *role < required) {
+        bot.send_message(
+            msg.chat.id,
+            format!("🚫 This command needs the {} role; you are a {}.", required.as_str(), role.as_str()),
+        ).await?;
+        return Ok(());
     }
 
     match cmd {
@@ -76,10 +102,17 @@ async fn handle_command(
             let s = state.lock().await;
             let uptime = s.start_time.elapsed();
             let last_reclaim = s.last_reclaim_summary.as_deref().unwrap_or("None");
+            let last_scan = s.secs_since_last_scan()
+                .map(|secs| format!("{} ago", crate::storage::format_age(secs)))
+                .unwrap_or_else(|| "never".to_string());
             let response = format!(
-                "📊 Stats:\n- Total Reclaimed: {} SOL\n- Accounts Closed: {}\n- Uptime: {:?}\n- Last Event: {}\n- Mode: {:?}\n- Dry Run: {}",
+                "📊 Stats:\n- Lifetime: {} SOL from {} accounts\n- Since Restart: {} SOL from {} accounts\n- Last Scan: {}\n- Scheduled Sweeps: {}\n- Uptime: {:?}\n- Last Event: {}\n- Mode: {:?}\n- Dry Run: {}",
+                s.lifetime_reclaimed_lamports as f64 / 1_000_000_000.0,
+                s.lifetime_accounts_closed,
                 s.total_reclaimed_lamports as f64 / 1_000_000_000.0,
                 s.total_accounts_closed,
+                last_scan,
+                sweep_schedule(&storage),
                 uptime,
                 last_reclaim,
                 config.mode,
@@ -89,8 +122,15 @@ async fn handle_command(
         }
         Command::Sweep => {
             let mut s = state.lock().await;
-            s.force_run = true;
-            bot.send_message(msg.chat.id, "Triggering manual sweep...").await?;
+            if s.mode == crate::config::AppMode::Real {
+                let nonce = s.confirmations.issue(PendingAction::Sweep, user_id, unix_now());
+                drop(s);
+                let text = format!("⚠️ Confirm real sweep\n{}", sweep_preview(&storage, &config));
+                bot.send_message(msg.chat.id, text).reply_markup(confirm_keyboard(&nonce)).await?;
+            } else {
+                s.force_run = true;
+                bot.send_message(msg.chat.id, "Triggering manual sweep...").await?;
+            }
         }
         Command::Log => {
             let logs = storage.get_recent_history(10).unwrap_or_else(|_| vec!["Failed to load logs".to_string()]);
@@ -101,20 +141,178 @@ async fn handle_command(
             };
             bot.send_message(msg.chat.id, response).await?;
         }
+        Command::Report => {
+            let response = match storage.latest_scan_report() {
+                Ok(Some(report)) => format!("🔎 {}", report.render(10)),
+                Ok(None) => "No scan has been recorded yet.".to_string(),
+                Err(_) => "Failed to load scan report".to_string(),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
+        Command::Preview => {
+            // Each preview is a full getProgramAccounts scan; never run two at once.
+            if std::mem::replace(&mut state.lock().await.preview_running, true) {
+                bot.send_message(msg.chat.id, "A preview is already running.").await?;
+                return Ok(());
+            }
+            let _ = bot.send_message(msg.chat.id, "🔎 Scanning...").await;
+            let preview = match storage.operator_keypair(&config.solana.keypair_path) {
+                Ok(keypair) => {
+                    let scanner = Scanner::new(&config.solana.rpc_url);
+                    preview_sweep(&scanner, keypair.pubkey(), &storage, &config.settings).await
+                }
+                Err(e) => Err(e),
+            };
+            state.lock().await.preview_running = false;
+            match preview {
+                Ok(preview) => {
+                    let full = preview.render(usize::MAX);
+                    if full.len() <= MAX_MESSAGE_LEN {
+                        bot.send_message(msg.chat.id, full).await?;
+                    } else {
+                        bot.send_message(msg.chat.id, preview.render(PREVIEW_TOP_ACCOUNTS)).await?;
+                        let file = InputFile::memory(full.into_bytes()).file_name("preview.txt");
+                        bot.send_document(msg.chat.id, file).caption("Full list of candidates").await?;
+                    }
+                }
+                Err(e) => {
+                    bot.send_message(msg.chat.id, format!("❌ Preview failed: {:#}", e)).await?;
+                }
+            }
+        }
+        Command::Pause(args) => {
+            let now = unix_now();
+            let until = match args.trim() {
+                "" => Ok(None),
+                duration => parse_duration(duration).map(|secs| Some(now + secs as i64)),
+            };
+            let response = match until {
+                Ok(until) => {
+                    let pause = Pause { paused_at: now, until, by: format!("telegram:{}", user_id) };
+                    match storage.set_pause(&pause) {
+                        Ok(()) => {
+                            let _ = storage.log_event(&format!("⏸️ Scheduled sweeps {} (by {})", pause.describe(now), pause.by));
+                            format!("⏸️ Scheduled sweeps {}. /sweep still runs on demand.", pause.describe(now))
+                        }
+                        Err(_) => "Failed to save the pause".to_string(),
+                    }
+                }
+                Err(e) => format!("❌ {}", e),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
+        Command::Resume => {
+            let response = match storage.resume() {
+                Ok(true) => {
+                    let _ = storage.log_event(&format!("▶️ Scheduled sweeps resumed (by telegram:{})", user_id));
+                    "▶️ Scheduled sweeps resumed.".to_string()
+                }
+                Ok(false) => "Scheduled sweeps were not paused.".to_string(),
+                Err(_) => "Failed to resume".to_string(),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
         Command::Mode => {
             let mut s = state.lock().await;
             if s.demo_only {
                 bot.send_message(msg.chat.id, "⚠️ **Action Denied**: This worker is locked in **Demo-Only** mode via system flags.").parse_mode(teloxide::types::ParseMode::MarkdownV2).await?;
+            } else if s.mode == crate::config::AppMode::Demo {
+                let nonce = s.confirmations.issue(PendingAction::EnableReal, user_id, unix_now());
+                drop(s);
+                let text = format!(
+                    "⚠️ Switch to Real mode? Sweeps will close accounts and send their rent to the treasury.\n{}",
+                    sweep_preview(&storage, &config)
+                );
+                bot.send_message(msg.chat.id, text).reply_markup(confirm_keyboard(&nonce)).await?;
             } else {
-                let new_mode = match s.mode {
-                    crate::config::AppMode::Demo => crate::config::AppMode::Real,
-                    crate::config::AppMode::Real => crate::config::AppMode::Demo,
-                };
-                s.mode = new_mode;
-                let msg_text = format!("🔄 Mode switched to: **{:?}**", new_mode);
+                s.mode = crate::config::AppMode::Demo;
+                let msg_text = format!("🔄 Mode switched to: **{:?}**", s.mode);
                 bot.send_message(msg.chat.id, msg_text).parse_mode(teloxide::types::ParseMode::MarkdownV2).await?;
             }
         }
+        Command::Users => {
+            let response = match storage.users() {
+                Ok(users) if users.is_empty() => "No users registered.".to_string(),
+                Ok(users) => {
+                    let lines: Vec<String> = users.iter()
+                        .map(|user| format!("- {} ({})", user.telegram_id, user.role.as_str()))
+                        .collect();
+                    format!("👥 Users:\n{}", lines.join("\n"))
+                }
+                Err(_) => "Failed to load users".to_string(),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
+        Command::Grant(args) => {
+            let parts: Vec<&str> = args.split_whitespace().collect();
+            let response = match parts.as_slice() {
+                [id, role] => match (id.parse::<u64>(), Role::parse(role)) {
+                    (Ok(id), Ok(role)) => match storage.set_user_role(id, role) {
+                        Ok(()) => {
+                            let _ = storage.log_event(&format!("👤 User {} granted {} by {}", id, role.as_str(), user_id));
+                            format!("✅ User {} is now a {}.", id, role.as_str())
+                        }
+                        Err(e) => format!("❌ {}", e),
+                    },
+                    (Err(_), _) => format!("❌ '{}' is not a Telegram user id.", id),
+                    (_, Err(e)) => format!("❌ {}", e),
+                },
+                _ => "Usage: /grant <user_id> <viewer|operator|owner>".to_string(),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
+        Command::Revoke(args) => {
+            let response = match args.trim().parse::<u64>() {
+                Ok(id) => match storage.revoke_user(id) {
+                    Ok(true) => {
+                        let _ = storage.log_event(&format!("👤 User {} revoked by {}", id, user_id));
+                        format!("✅ User {} no longer has access.", id)
+                    }
+                    Ok(false) => format!("User {} was not registered.", id),
+                    Err(e) => format!("❌ {}", e),
+                },
+                Err(_) => "Usage: /revoke <user_id>".to_string(),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
+        Command::Whitelist(args) => {
+            let response = match WhitelistAction::parse(&args) {
+                Ok(WhitelistAction::Add(rule)) => match storage.add_rule(&rule) {
+                    Ok(true) => {
+                        let _ = storage.log_event(&format!("🛡️ Whitelisted {} {} by {}", rule.kind(), rule.value(), user_id));
+                        format!("✅ Whitelisted {} {}. Takes effect on the next scan.", rule.kind(), rule.value())
+                    }
+                    Ok(false) => format!("{} {} is already whitelisted.", rule.kind(), rule.value()),
+                    Err(_) => "Failed to save the whitelist entry".to_string(),
+                },
+                Ok(WhitelistAction::Remove(rule)) => match storage.remove_rule(&rule) {
+                    Ok(true) => {
+                        let _ = storage.log_event(&format!("🛡️ Removed {} {} from the whitelist by {}", rule.kind(), rule.value(), user_id));
+                        format!("✅ Removed {} {} from the whitelist. Takes effect on the next scan.", rule.kind(), rule.value())
+                    }
+                    Ok(false) => format!("{} {} is not on the whitelist.", rule.kind(), rule.value()),
+                    Err(_) => "Failed to update the whitelist".to_string(),
+                },
+                Ok(WhitelistAction::List) => match storage.list_rules() {
+                    Ok(rules) => {
+                        let mut lines: Vec<String> = rules.iter()
+                            .filter(|rule| matches!(rule, Rule::ProtectAccount(_) | Rule::ProtectMint(_)))
+                            .map(|rule| format!("- {} {}", rule.kind(), rule.value()))
+                            .collect();
+                        // Entries from config.toml can only be changed there.
+                        lines.extend(config.settings.whitelist.iter().map(|account| format!("- account {} (config)", account)));
+                        if lines.is_empty() {
+                            "The whitelist is empty.".to_string()
+                        } else {
+                            format!("🛡️ Whitelist:\n{}", lines.join("\n"))
+                        }
+                    }
+                    Err(_) => "Failed to load the whitelist".to_string(),
+                },
+                Err(e) => format!("❌ {}", e),
+            };
+            bot.send_message(msg.chat.id, response).await?;
+        }
         Command::Help => {
             let s = state.lock().await;
             let mode_info = if s.demo_only {
@@ -132,15 +330,20 @@ async fn handle_command(
                 /stats - View reclamation metrics\n\
                 /sweep - Trigger an immediate scan\n\
                 /log   - View recent event history\n\
+                /report - View the last scan report\n\
+                /preview - Show what a sweep would close\n\
+                /pause /resume - Stop or restart scheduled sweeps\n\
                 /mode  - Switch modes (if not locked)\n\
                 /host  - Learn how to run your own instance\n\
-                /health - Check system status\n\n\
+                /health - Check system status\n\
+                /whitelist - List or change protected accounts\n\
+                /users /grant /revoke - Manage who can use this bot (owners)\n\n\
                 **Secure Setup:**\n\
                 To use your own keys, import them into your local instance:\n\
                 `koralreef --import-key <path_to_keypair.json>`",
                 mode_info
             );
-            bot.send_message(msg.chat.id, help_text).parse_mode(teloxide::types::ParseMode::Markdown).await?;
+            bot.send_message(msg.chat.id, help_text).parse_mode(LEGACY_MARKDOWN).await?;
         }
         Command::Host => {
             let host_text = "🏠 **Self-Hosting koralreef**\n\n\
@@ -152,7 +355,7 @@ async fn handle_command(
                 2. **Custom Whitelist:** Prevent accidental closure of critical accounts.\n\
                 3. **Privacy:** Your operational logs remain on your hardware.\n\n\
                 Check the [GitHub Repository](https://github.com/nathfavour/koralReef) for detailed setup guides.";
-            bot.send_message(msg.chat.id, host_text).parse_mode(teloxide::types::ParseMode::Markdown).await?;
+            bot.send_message(msg.chat.id, host_text).parse_mode(LEGACY_MARKDOWN).await?;
         }
         Command::Health => {
             let s = state.lock().await;
@@ -160,16 +363,118 @@ async fn handle_command(
             let health_text = format!(
                 "🏥 **System Health**\n\n\
                 - **Status:** {}\n\
+                - **Scheduled Sweeps:** {}\n\
                 - **Mode:** {:?}\n\
                 - **Uptime:** {:?}\n\
                 - **Scanner:** Functional\n\
                 - **RPC Endpoint:** Connected\n\n\
                 *All systems operational.*",
-                status, s.mode, s.start_time.elapsed()
+                status, sweep_schedule(&storage), s.mode, s.start_time.elapsed()
             );
-            bot.send_message(msg.chat.id, health_text).parse_mode(teloxide::types::ParseMode::Markdown).await?;
+            bot.send_message(msg.chat.id, health_text).parse_mode(LEGACY_MARKDOWN).await?;
         }
     }
 
     Ok(())
 }
+
+/// What the next real sweep is expected to close, from the latest scan.
+/// The sweep itself scans again, so this is an estimate.
+fn sweep_preview(storage: &Storage, config: &Config) -> String {
+    let estimate = match storage.latest_scan_report() {
+        Ok(Some(report)) => format!(
+            "- Accounts: {}\n- Estimated: {} SOL (scan at {} UTC)",
+            report.reclaimable,
+            report.reclaimable_lamports() as f64 / 1_000_000_000.0,
+            report.timestamp
+        ),
+        Ok(None) => "- Accounts: unknown, no scan has been recorded yet".to_string(),
+        Err(_) => "- Accounts: unknown, failed to load the last scan".to_string(),
+    };
+    format!(
+        "{}\n- Treasury: {}\nExpires in {}s.",
+        estimate, config.solana.treasury_address, CONFIRM_TTL_SECS
+    )
+}
+
+fn confirm_keyboard(nonce: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("✅ Confirm", format!("{}{}", CONFIRM_PREFIX, nonce)),
+        InlineKeyboardButton::callback("✖️ Cancel", format!("{}{}", CANCEL_PREFIX, nonce)),
+    ]])
+}
+
+/// Handles taps on the Confirm and Cancel buttons sent by `confirm_keyboard`.
+async fn handle_callback(
+    bot: Bot,
+    q: CallbackQuery,
+    state: SharedState,
+    storage: Arc<Storage>,
+) -> ResponseResult<()> {
+    let user_id = q.from.id.0;
+    let data = q.data.as_deref().unwrap_or_default();
+    let (confirmed, nonce) = if let Some(nonce) = data.strip_prefix(CONFIRM_PREFIX) {
+        (true, nonce)
+    } else if let Some(nonce) = data.strip_prefix(CANCEL_PREFIX) {
+        (false, nonce)
+    } else {
+        bot.answer_callback_query(q.id).await?;
+        return Ok(());
+    };
+
+    let taken = state.lock().await.confirmations.take(nonce, user_id, unix_now());
+    let text = match taken {
+        Err(ConfirmError::WrongUser) => {
+            bot.answer_callback_query(q.id).text(ConfirmError::WrongUser.to_string()).await?;
+            return Ok(());
+        }
+        Err(e) => format!("⌛ {}", e),
+        Ok(_) if !confirmed => "✖️ Cancelled. Nothing was changed.".to_string(),
+        Ok(action) => {
+            let allowed = matches!(storage.user_role(user_id), Ok(Some(role)) if role >= action.required_role());
+            if allowed {
+                run_confirmed(action, user_id, &state, &storage).await
+            } else {
+                "🚫 You no longer have access to do that.".to_string()
+            }
+        }
+    };
+
+    bot.answer_callback_query(q.id).await?;
+    if let Some(message) = q.message {
+        // Replacing the text also removes the buttons.
+        bot.edit_message_text(message.chat.id, message.id, text).await?;
+    }
+    Ok(())
+}
+
+async fn run_confirmed(action: PendingAction, user_id: u64, state: &SharedState, storage: &Storage) -> String {
+    let mut s = state.lock().await;
+    match action {
+        PendingAction::Sweep => {
+            s.force_run = true;
+            let _ = storage.log_event(&format!("🧹 Real sweep confirmed by {}", user_id));
+            "✅ Confirmed. Triggering manual sweep...".to_string()
+        }
+        PendingAction::EnableReal if s.demo_only => {
+            "⚠️ Action Denied: This worker is locked in Demo-Only mode via system flags.".to_string()
+        }
+        PendingAction::EnableReal => {
+            s.mode = crate::config::AppMode::Real;
+            // A demo `/sweep`, possibly from a guest, must not become a real one.
+            s.force_run = false;
+            let _ = storage.log_event(&format!("🔄 Real mode enabled by {}", user_id));
+            "✅ Confirmed. Mode switched to: Real".to_string()
+        }
+    }
+}
+
+/// "running", or how long scheduled sweeps stay paused.
+fn sweep_schedule(storage: &Storage) -> String {
+    let now = unix_now();
+    match storage.pause() {
+        Ok(Some(pause)) if !pause.has_ended(now) => pause.describe(now),
+        Ok(_) => "running".to_string(),
+        Err(_) => "unknown".to_string(),
+    }
+}
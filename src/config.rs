@@ -35,6 +35,20 @@ pub struct Settings {
     pub dry_run: bool,
     pub demo_only: Option<bool>,
     pub whitelist: Vec<String>,
+    /// Compute unit limit per close transaction. Defaults to a per-close estimate.
+    pub compute_unit_limit: Option<u32>,
+    /// Fixed priority fee in micro-lamports per compute unit.
+    pub compute_unit_price_micro_lamports: Option<u64>,
+    /// Derive the priority fee from recent prioritization fees instead.
+    pub auto_priority_fee: Option<bool>,
+    /// Percentile of recent fees used by `auto_priority_fee` (default 75).
+    pub priority_fee_percentile: Option<u8>,
+    /// Upper bound on priority fees spent in a single sweep.
+    pub max_priority_fee_lamports_per_sweep: Option<u64>,
+    /// Batches whose rent minus fees falls below this are deferred.
+    pub min_net_profit_lamports: Option<u64>,
+    /// How long an account must stay empty before it is closed.
+    pub grace_period_minutes: Option<u64>,
 }
 
 impl Config {
@@ -64,6 +78,13 @@ impl Config {
                 dry_run: true,
                 demo_only: Some(true),
                 whitelist: vec![],
+                compute_unit_limit: None,
+                compute_unit_price_micro_lamports: None,
+                auto_priority_fee: None,
+                priority_fee_percentile: None,
+                max_priority_fee_lamports_per_sweep: None,
+                min_net_profit_lamports: None,
+                grace_period_minutes: None,
             },
         }
     }
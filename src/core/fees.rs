@@ -0,0 +1,90 @@
+use crate::config::Settings;
+
+/// Base fee charged per transaction signature.
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// Compute units budgeted per close instruction when no limit is configured.
+pub const DEFAULT_UNITS_PER_CLOSE: u32 = 10_000;
+
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+
+/// Compute budget and priority fee settings for close transactions.
+#[derive(Debug, Clone, Default)]
+pub struct FeePolicy {
+    pub compute_unit_limit: Option<u32>,
+    /// Fixed price in micro-lamports per compute unit.
+    pub compute_unit_price: Option<u64>,
+    /// Derive the price from `getRecentPrioritizationFees` instead.
+    pub auto_priority_fee: bool,
+    pub priority_fee_percentile: u8,
+    pub max_priority_fee_per_sweep: Option<u64>,
+    pub min_net_profit: u64,
+}
+
+impl FeePolicy {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            compute_unit_limit: settings.compute_unit_limit,
+            compute_unit_price: settings.compute_unit_price_micro_lamports,
+            auto_priority_fee: settings.auto_priority_fee.unwrap_or(false),
+            priority_fee_percentile: settings.priority_fee_percentile.unwrap_or(75).min(100),
+            max_priority_fee_per_sweep: settings.max_priority_fee_lamports_per_sweep,
+            min_net_profit: settings.min_net_profit_lamports.unwrap_or(0),
+        }
+    }
+
+    /// Whether close transactions need `ComputeBudget` instructions at all.
+    pub fn is_enabled(&self) -> bool {
+        self.compute_unit_limit.is_some() || self.compute_unit_price.is_some() || self.auto_priority_fee
+    }
+
+    pub fn unit_limit(&self, closes: usize) -> u32 {
+        self.compute_unit_limit
+            .unwrap_or(DEFAULT_UNITS_PER_CLOSE.saturating_mul(closes as u32))
+    }
+
+    /// Whether recovering `lamports` is worth paying `fee` for.
+    pub fn is_profitable(&self, lamports: u64, fee: u64) -> bool {
+        lamports > fee && lamports - fee >= self.min_net_profit
+    }
+
+    /// Picks the price for one batch so that priority fees stay within the
+    /// per-sweep cap and leave at least the minimum net profit, and never less
+    /// than a lamport, of the rent the batch recovers. `spent` is the priority
+    /// fee already committed earlier in the sweep.
+    pub fn batch_price(&self, price: u64, units: u32, batch_lamports: u64, spent: u64) -> u64 {
+        if price == 0 || units == 0 {
+            return 0;
+        }
+
+        let mut affordable = batch_lamports
+            .saturating_sub(LAMPORTS_PER_SIGNATURE)
+            // `is_profitable` wants the rent to exceed the fee.
+            .saturating_sub(self.min_net_profit.max(1));
+        if let Some(cap) = self.max_priority_fee_per_sweep {
+            affordable = affordable.min(cap.saturating_sub(spent));
+        }
+
+        if priority_fee(units, price) <= affordable {
+            price
+        } else {
+            (affordable as u128 * MICRO_LAMPORTS_PER_LAMPORT / units as u128) as u64
+        }
+    }
+}
+
+/// Lamports charged for `units` compute units at `price` micro-lamports each.
+pub fn priority_fee(units: u32, price: u64) -> u64 {
+    let micro = units as u128 * price as u128;
+    micro.div_ceil(MICRO_LAMPORTS_PER_LAMPORT) as u64
+}
+
+/// Nearest-rank percentile of recent prioritization fees.
+pub fn percentile(mut fees: Vec<u64>, percentile: u8) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    let rank = (percentile as usize * fees.len()).div_ceil(100).max(1);
+    fees[rank.min(fees.len()) - 1]
+}
@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_client::rpc_response::{RpcPrioritizationFee, RpcSimulateTransactionResult};
+use solana_sdk::account::{Account, AccountSharedData};
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::program_option::COption;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_transaction_status::{TransactionConfirmationStatus, TransactionStatus};
+use spl_token_2022::extension::{ExtensionType, StateWithExtensions, StateWithExtensionsMut};
+use spl_token_2022::extension::confidential_transfer::ConfidentialTransferAccount;
+use spl_token_2022::extension::mint_close_authority::MintCloseAuthority;
+use spl_token_2022::extension::transfer_fee::TransferFeeAmount;
+use spl_token_2022::error::TokenError;
+use spl_token_2022::instruction::TokenInstruction;
+use spl_token_2022::state::{Account as TokenAccount, AccountState, Mint};
+use super::{token_account_filters, LedgerClient};
+use crate::core::fees::{self, LAMPORTS_PER_SIGNATURE};
+
+/// Compute units charged per close instruction by the in-memory ledger.
+pub const UNITS_PER_CLOSE: u64 = 3_000;
+
+const DEFAULT_UNITS_PER_INSTRUCTION: u32 = 200_000;
+
+/// Fields of a token account beyond its mint, owner and balance, for
+/// [`InMemoryLedger::add_token_account_with`].
+#[derive(Debug, Clone, Default)]
+pub struct TokenAccountOptions {
+    pub frozen: bool,
+    /// Makes it a native (wSOL) account with this rent-exempt reserve.
+    pub native: Option<u64>,
+    pub delegate: Option<Pubkey>,
+    pub close_authority: Option<Pubkey>,
+    /// Token-2022 only.
+    pub extensions: Vec<TokenExtension>,
+}
+
+/// A Token-2022 account extension and the value it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenExtension {
+    /// Tokens withheld from transfers, waiting to be harvested to the mint.
+    TransferFeeAmount(u64),
+    ConfidentialTransfer { has_balance: bool },
+}
+
+#[derive(Default, Clone)]
+struct LedgerState {
+    accounts: HashMap<Pubkey, Account>,
+    balances: HashMap<Pubkey, u64>,
+    processed: HashMap<Signature, u64>,
+    /// Landed on a fork that has not been confirmed yet, so reported at
+    /// `processed` only.
+    unconfirmed: HashSet<Signature>,
+    slot: u64,
+    blockhash: Hash,
+}
+
+/// A ledger held entirely in memory. It knows token accounts, SOL balances
+/// and how to execute `CloseAccount` and `ComputeBudget` instructions, which
+/// is enough to run a full sweep without a network.
+#[derive(Default)]
+pub struct InMemoryLedger {
+    state: Mutex<LedgerState>,
+    prioritization_fees: Mutex<Vec<u64>>,
+    /// `get_latest_blockhash` calls left before the RPC "goes down".
+    blockhash_budget: Mutex<Option<usize>>,
+    /// State before the first unconfirmed transaction, restored when its
+    /// fork is dropped. Set while confirmations are held.
+    fork_base: Mutex<Option<LedgerState>>,
+}
+
+impl InMemoryLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_account(&self, pubkey: Pubkey, account: Account) {
+        self.state.lock().unwrap().accounts.insert(pubkey, account);
+    }
+
+    /// Creates an initialized token account and returns its address. The
+    /// base layout is shared by SPL Token and Token-2022.
+    pub fn add_token_account(&self, program_id: &Pubkey, owner: &Pubkey, mint: &Pubkey, amount: u64, lamports: u64) -> Pubkey {
+        self.add_token_account_with(program_id, owner, mint, amount, lamports, TokenAccountOptions::default())
+    }
+
+    /// Like [`Self::add_token_account`], with the fields in `options` set.
+    /// Extensions are packed after the base layout, so such accounts are
+    /// longer than 165 bytes.
+    pub fn add_token_account_with(&self, program_id: &Pubkey, owner: &Pubkey, mint: &Pubkey, amount: u64, lamports: u64, options: TokenAccountOptions) -> Pubkey {
+        assert!(options.extensions.is_empty() || *program_id == spl_token_2022::id(), "only Token-2022 accounts have extensions");
+        let base = TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            delegate: options.delegate.into(),
+            state: if options.frozen { AccountState::Frozen } else { AccountState::Initialized },
+            is_native: options.native.into(),
+            delegated_amount: if options.delegate.is_some() { 1 } else { 0 },
+            close_authority: options.close_authority.into(),
+        };
+
+        let data = if options.extensions.is_empty() {
+            let mut data = vec![0u8; TokenAccount::LEN];
+            TokenAccount::pack(base, &mut data).expect("token account fits its own length");
+            data
+        } else {
+            let types: Vec<ExtensionType> = options.extensions.iter()
+                .map(|ext| match ext {
+                    TokenExtension::TransferFeeAmount(_) => ExtensionType::TransferFeeAmount,
+                    TokenExtension::ConfidentialTransfer { .. } => ExtensionType::ConfidentialTransferAccount,
+                })
+                .collect();
+            let len = ExtensionType::try_calculate_account_len::<TokenAccount>(&types).expect("known extensions");
+            let mut data = vec![0u8; len];
+            let mut state = StateWithExtensionsMut::<TokenAccount>::unpack_uninitialized(&mut data).expect("sized for its extensions");
+            for ext in &options.extensions {
+                match *ext {
+                    TokenExtension::TransferFeeAmount(withheld) => {
+                        state.init_extension::<TransferFeeAmount>(true).expect("space reserved").withheld_amount = withheld.into();
+                    }
+                    TokenExtension::ConfidentialTransfer { has_balance } => {
+                        let ext = state.init_extension::<ConfidentialTransferAccount>(true).expect("space reserved");
+                        if has_balance {
+                            ext.available_balance.0[0] = 1;
+                        }
+                    }
+                }
+            }
+            state.base = base;
+            state.pack_base();
+            state.init_account_type().expect("account type fits");
+            data
+        };
+        self.add_account(program_id, data, lamports)
+    }
+
+    /// Creates an initialized Token-2022 mint with a close authority
+    /// extension and returns its address.
+    pub fn add_token_2022_mint(&self, mint_authority: &Pubkey, lamports: u64) -> Pubkey {
+        let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::MintCloseAuthority]).expect("known extension");
+        let mut data = vec![0u8; len];
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).expect("sized for its extension");
+        state.init_extension::<MintCloseAuthority>(true).expect("space reserved").close_authority =
+            Some(*mint_authority).try_into().expect("a key is never the zero default");
+        state.base = Mint {
+            mint_authority: COption::Some(*mint_authority),
+            decimals: 6,
+            is_initialized: true,
+            ..Default::default()
+        };
+        state.pack_base();
+        state.init_account_type().expect("account type fits");
+        self.add_account(&spl_token_2022::id(), data, lamports)
+    }
+
+    fn add_account(&self, program_id: &Pubkey, data: Vec<u8>, lamports: u64) -> Pubkey {
+        let pubkey = Pubkey::new_unique();
+        self.set_account(pubkey, Account {
+            lamports,
+            data,
+            owner: *program_id,
+            executable: false,
+            rent_epoch: 0,
+        });
+        pubkey
+    }
+
+    pub fn account(&self, pubkey: &Pubkey) -> Option<Account> {
+        self.state.lock().unwrap().accounts.get(pubkey).cloned()
+    }
+
+    /// Sets the SOL balance of a wallet (a system account with no data).
+    pub fn set_balance(&self, pubkey: &Pubkey, lamports: u64) {
+        self.state.lock().unwrap().balances.insert(*pubkey, lamports);
+    }
+
+    pub fn balance(&self, pubkey: &Pubkey) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.accounts.get(pubkey)
+            .map(|a| a.lamports)
+            .or_else(|| state.balances.get(pubkey).copied())
+            .unwrap_or(0)
+    }
+
+    pub fn set_prioritization_fees(&self, fees: Vec<u64>) {
+        *self.prioritization_fees.lock().unwrap() = fees;
+    }
+
+    /// Replaces the latest blockhash, so transactions signed with an earlier
+    /// one can no longer land.
+    pub fn expire_blockhash(&self) {
+        self.state.lock().unwrap().blockhash = Hash::new_unique();
+    }
+
+    /// Makes `get_latest_blockhash` fail after `calls` more successful calls,
+    /// as when the RPC node becomes unreachable partway through a sweep.
+    pub fn fail_blockhash_after(&self, calls: usize) {
+        *self.blockhash_budget.lock().unwrap() = Some(calls);
+    }
+
+    /// Leaves transactions sent from now on at `processed`, as if they landed
+    /// on a fork that may still be dropped.
+    pub fn hold_confirmations(&self) {
+        let state = self.state.lock().unwrap();
+        *self.fork_base.lock().unwrap() = Some(state.clone());
+    }
+
+    /// Confirms the held transactions.
+    pub fn confirm_held(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.unconfirmed.clear();
+        *self.fork_base.lock().unwrap() = None;
+    }
+
+    /// Drops the fork holding the unconfirmed transactions: their effects are
+    /// undone, they are forgotten and their blockhash expires.
+    pub fn drop_held(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(base) = self.fork_base.lock().unwrap().take() {
+            *state = base;
+        }
+        state.blockhash = Hash::new_unique();
+    }
+
+    /// Number of transactions that have landed.
+    pub fn transaction_count(&self) -> usize {
+        self.state.lock().unwrap().processed.len()
+    }
+
+    /// Runs `tx` against `state`. On error `state` may be partially modified,
+    /// so callers execute against a copy and only keep it on success.
+    fn execute(state: &mut LedgerState, tx: &Transaction) -> (Result<(), TransactionError>, u64, Vec<String>) {
+        let mut logs = Vec::new();
+        let mut units = 0;
+        if tx.verify().is_err() {
+            return (Err(TransactionError::SignatureFailure), units, logs);
+        }
+        if tx.message.recent_blockhash != state.blockhash {
+            return (Err(TransactionError::BlockhashNotFound), units, logs);
+        }
+
+        let message = &tx.message;
+        let keys = &message.account_keys;
+        let mut unit_limit = None;
+        let mut unit_price = 0;
+        for ix in &message.instructions {
+            if keys[ix.program_id_index as usize] == solana_sdk::compute_budget::id() {
+                match ix.data.first() {
+                    Some(2) if ix.data.len() >= 5 => unit_limit = Some(u32::from_le_bytes(ix.data[1..5].try_into().unwrap())),
+                    Some(3) if ix.data.len() >= 9 => unit_price = u64::from_le_bytes(ix.data[1..9].try_into().unwrap()),
+                    _ => {}
+                }
+            }
+        }
+        let limit = unit_limit.unwrap_or(DEFAULT_UNITS_PER_INSTRUCTION.saturating_mul(message.instructions.len() as u32));
+        let fee = LAMPORTS_PER_SIGNATURE * message.header.num_required_signatures as u64 + fees::priority_fee(limit, unit_price);
+
+        let payer = keys[0];
+        let payer_balance = state.balances.get(&payer).copied().unwrap_or(0);
+        if payer_balance < fee {
+            return (Err(TransactionError::InsufficientFundsForFee), units, logs);
+        }
+        state.balances.insert(payer, payer_balance - fee);
+
+        for (index, ix) in message.instructions.iter().enumerate() {
+            let program_id = keys[ix.program_id_index as usize];
+            logs.push(format!("Program {} invoke [1]", program_id));
+            if program_id == solana_sdk::compute_budget::id() {
+                units += 150;
+            } else if program_id == spl_token::id() || program_id == spl_token_2022::id() {
+                units += UNITS_PER_CLOSE;
+                let accounts: Vec<Pubkey> = ix.accounts.iter().map(|i| keys[*i as usize]).collect();
+                let authority_signed = ix.accounts.get(2).map(|i| message.is_signer(*i as usize)).unwrap_or(false);
+                logs.push("Program log: Instruction: CloseAccount".to_string());
+                if let Err(e) = Self::close(state, &program_id, &ix.data, &accounts, authority_signed) {
+                    logs.push(format!("Program {} failed: {}", program_id, e));
+                    return (Err(TransactionError::InstructionError(index as u8, e)), units, logs);
+                }
+            } else {
+                return (Err(TransactionError::InstructionError(index as u8, InstructionError::IncorrectProgramId)), units, logs);
+            }
+            logs.push(format!("Program {} success", program_id));
+        }
+
+        (Ok(()), units, logs)
+    }
+
+    fn close(state: &mut LedgerState, program_id: &Pubkey, data: &[u8], accounts: &[Pubkey], authority_signed: bool) -> Result<(), InstructionError> {
+        match TokenInstruction::unpack(data) {
+            Ok(TokenInstruction::CloseAccount) => {}
+            _ => return Err(InstructionError::InvalidInstructionData),
+        }
+        let [source, destination, authority] = accounts else {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        };
+
+        let account = state.accounts.get(source).ok_or(InstructionError::UninitializedAccount)?;
+        if account.owner != *program_id {
+            return Err(InstructionError::IncorrectProgramId);
+        }
+        let token = StateWithExtensions::<TokenAccount>::unpack(&account.data)
+            .map_err(|_| InstructionError::InvalidAccountData)?
+            .base;
+        if !token.is_native() && token.amount != 0 {
+            return Err(InstructionError::Custom(TokenError::NonNativeHasBalance as u32));
+        }
+        let close_authority: Option<Pubkey> = token.close_authority.into();
+        if close_authority.unwrap_or(token.owner) != *authority {
+            return Err(InstructionError::Custom(TokenError::OwnerMismatch as u32));
+        }
+        if !authority_signed {
+            return Err(InstructionError::MissingRequiredSignature);
+        }
+
+        let lamports = account.lamports;
+        state.accounts.remove(source);
+        match state.accounts.get_mut(destination) {
+            Some(dest) => dest.lamports += lamports,
+            None => *state.balances.entry(*destination).or_default() += lamports,
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LedgerClient for InMemoryLedger {
+    async fn get_token_accounts(&self, program_id: &Pubkey, owner: &Pubkey) -> Result<Vec<(Pubkey, Account)>> {
+        // The same filters as the RPC query, so a wrong one loses accounts here too.
+        let filters = token_account_filters(program_id, owner);
+        let state = self.state.lock().unwrap();
+        Ok(state.accounts.iter()
+            .filter(|(_, a)| a.owner == *program_id)
+            .filter(|(_, a)| {
+                let shared = AccountSharedData::from((*a).clone());
+                filters.iter().all(|f| f.allows(&shared))
+            })
+            .map(|(p, a)| (*p, a.clone()))
+            .collect())
+    }
+
+    async fn get_balance(&self, pubkey: &Pubkey) -> Result<u64> {
+        Ok(self.balance(pubkey))
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash> {
+        if let Some(budget) = self.blockhash_budget.lock().unwrap().as_mut() {
+            if *budget == 0 {
+                anyhow::bail!("RPC node unreachable");
+            }
+            *budget -= 1;
+        }
+        Ok(self.state.lock().unwrap().blockhash)
+    }
+
+    async fn simulate_transaction(&self, tx: &Transaction) -> Result<RpcSimulateTransactionResult> {
+        let mut scratch = self.state.lock().unwrap().clone();
+        let (result, units, logs) = Self::execute(&mut scratch, tx);
+        Ok(RpcSimulateTransactionResult {
+            err: result.err(),
+            logs: Some(logs),
+            accounts: None,
+            units_consumed: Some(units),
+            return_data: None,
+            inner_instructions: None,
+        })
+    }
+
+    async fn send_transaction(&self, tx: &Transaction) -> Result<Signature> {
+        let mut state = self.state.lock().unwrap();
+        let mut scratch = state.clone();
+        let (result, _, _) = Self::execute(&mut scratch, tx);
+        result?;
+
+        scratch.slot += 1;
+        let signature = tx.signatures[0];
+        let slot = scratch.slot;
+        scratch.processed.insert(signature, slot);
+        if self.fork_base.lock().unwrap().is_some() {
+            scratch.unconfirmed.insert(signature);
+        }
+        *state = scratch;
+        Ok(signature)
+    }
+
+    async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<TransactionStatus>>> {
+        let state = self.state.lock().unwrap();
+        Ok(signatures.iter()
+            .map(|sig| state.processed.get(sig).map(|slot| TransactionStatus {
+                slot: *slot,
+                confirmations: None,
+                status: Ok(()),
+                err: None,
+                confirmation_status: Some(if state.unconfirmed.contains(sig) {
+                    TransactionConfirmationStatus::Processed
+                } else {
+                    TransactionConfirmationStatus::Finalized
+                }),
+            }))
+            .collect())
+    }
+
+    async fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool> {
+        Ok(self.state.lock().unwrap().blockhash == *blockhash)
+    }
+
+    async fn get_recent_prioritization_fees(&self, _addresses: &[Pubkey]) -> Result<Vec<RpcPrioritizationFee>> {
+        Ok(self.prioritization_fees.lock().unwrap().iter()
+            .enumerate()
+            .map(|(slot, fee)| RpcPrioritizationFee { slot: slot as u64, prioritization_fee: *fee })
+            .collect())
+    }
+}
@@ -0,0 +1,118 @@
+pub mod memory;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use solana_client::client_error::ClientError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSimulateTransactionConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_client::rpc_response::{RpcPrioritizationFee, RpcSimulateTransactionResult};
+use solana_sdk::account::Account;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_transaction_status::TransactionStatus;
+
+pub use memory::InMemoryLedger;
+
+/// The ledger operations the scanner and reclaimer depend on. Implemented by
+/// the nonblocking Solana `RpcClient` and by [`InMemoryLedger`] for tests.
+#[async_trait]
+pub trait LedgerClient: Send + Sync {
+    /// Token accounts of `program_id` whose owner field is `owner`. For
+    /// Token-2022 this may also return mints, which callers filter out.
+    async fn get_token_accounts(&self, program_id: &Pubkey, owner: &Pubkey) -> Result<Vec<(Pubkey, Account)>>;
+
+    async fn get_balance(&self, pubkey: &Pubkey) -> Result<u64>;
+
+    async fn get_latest_blockhash(&self) -> Result<Hash>;
+
+    async fn simulate_transaction(&self, tx: &Transaction) -> Result<RpcSimulateTransactionResult>;
+
+    /// Broadcasts `tx` without waiting for it to land.
+    async fn send_transaction(&self, tx: &Transaction) -> Result<Signature>;
+
+    /// Statuses of `signatures`, searching the full transaction history so
+    /// that batches sent long ago can still be found.
+    async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<TransactionStatus>>>;
+
+    /// Whether a transaction using `blockhash` could still land.
+    async fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool>;
+
+    async fn get_recent_prioritization_fees(&self, addresses: &[Pubkey]) -> Result<Vec<RpcPrioritizationFee>>;
+}
+
+/// The transaction error behind a failed ledger call, if there is one. Used to
+/// find the instruction that made a transaction fail.
+pub fn transaction_error(err: &anyhow::Error) -> Option<TransactionError> {
+    if let Some(tx_err) = err.downcast_ref::<TransactionError>() {
+        return Some(tx_err.clone());
+    }
+    err.downcast_ref::<ClientError>().and_then(|e| e.get_transaction_error())
+}
+
+/// The `getProgramAccounts` filters that find the token accounts of `owner`.
+fn token_account_filters(program_id: &Pubkey, owner: &Pubkey) -> Vec<RpcFilterType> {
+    let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new(
+        32,
+        MemcmpEncodedBytes::Base58(owner.to_string()),
+    ))];
+    // Legacy token accounts are always exactly 165 bytes. Token-2022
+    // accounts may carry extensions after the base layout, so their size varies.
+    if *program_id == spl_token::id() {
+        filters.push(RpcFilterType::DataSize(165));
+    }
+    filters
+}
+
+#[async_trait]
+impl LedgerClient for RpcClient {
+    async fn get_token_accounts(&self, program_id: &Pubkey, owner: &Pubkey) -> Result<Vec<(Pubkey, Account)>> {
+        Ok(self.get_program_accounts_with_config(
+            program_id,
+            RpcProgramAccountsConfig {
+                filters: Some(token_account_filters(program_id, owner)),
+                account_config: RpcAccountInfoConfig {
+                    encoding: Some(solana_account_decoder::UiAccountEncoding::Base64),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ).await?)
+    }
+
+    async fn get_balance(&self, pubkey: &Pubkey) -> Result<u64> {
+        Ok(RpcClient::get_balance(self, pubkey).await?)
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash> {
+        Ok(RpcClient::get_latest_blockhash(self).await?)
+    }
+
+    async fn simulate_transaction(&self, tx: &Transaction) -> Result<RpcSimulateTransactionResult> {
+        Ok(self.simulate_transaction_with_config(
+            tx,
+            RpcSimulateTransactionConfig {
+                sig_verify: true,
+                ..Default::default()
+            },
+        ).await?.value)
+    }
+
+    async fn send_transaction(&self, tx: &Transaction) -> Result<Signature> {
+        Ok(RpcClient::send_transaction(self, tx).await?)
+    }
+
+    async fn get_signature_statuses(&self, signatures: &[Signature]) -> Result<Vec<Option<TransactionStatus>>> {
+        Ok(self.get_signature_statuses_with_history(signatures).await?.value)
+    }
+
+    async fn is_blockhash_valid(&self, blockhash: &Hash) -> Result<bool> {
+        Ok(RpcClient::is_blockhash_valid(self, blockhash, self.commitment()).await?)
+    }
+
+    async fn get_recent_prioritization_fees(&self, addresses: &[Pubkey]) -> Result<Vec<RpcPrioritizationFee>> {
+        Ok(RpcClient::get_recent_prioritization_fees(self, addresses).await?)
+    }
+}
@@ -1,3 +1,6 @@
 pub mod scanner;
 pub mod reclaimer;
 pub mod safety;
+pub mod fees;
+pub mod ledger;
+pub mod sweep;
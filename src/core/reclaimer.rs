@@ -1,54 +1,371 @@
-use solana_client::rpc_client::RpcClient;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::stream::{self, StreamExt};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::{Keypair, Signer};
-use solana_sdk::transaction::Transaction;
-use spl_token::instruction::close_account;
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use spl_token_2022::instruction::close_account;
 use anyhow::Result;
-use log::{info, error};
+use log::{info, warn, error};
+use crate::core::fees::{self, FeePolicy, LAMPORTS_PER_SIGNATURE};
+use crate::core::ledger::{self, LedgerClient};
+use crate::storage::{JournalEntry, JournalStatus, Storage};
+use tokio_util::sync::CancellationToken;
+
+/// Close transactions in flight at the same time.
+const MAX_CONCURRENT_BATCHES: usize = 4;
+
+/// How often an unconfirmed signature is polled.
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What became of a broadcast transaction.
+enum Landing {
+    /// Confirmed on chain, with the error it failed with, if any.
+    Landed { slot: u64, err: Option<TransactionError> },
+    /// Not seen on chain and its blockhash has expired, so it never will be.
+    Expired,
+}
+
+/// A single close transaction and the rent it recovered.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    /// `None` when the batch was only planned during a dry run.
+    pub signature: Option<Signature>,
+    /// Slot the transaction landed in, `None` during a dry run.
+    pub slot: Option<u64>,
+    pub accounts: Vec<Pubkey>,
+    /// Sum of the pre-close lamports of `accounts`, as fetched by the scanner.
+    pub lamports: u64,
+    /// Signature fee plus priority fee paid by the operator for this batch.
+    pub fee_lamports: u64,
+}
+
+/// An account that made its batch fail and was pulled out of it.
+#[derive(Debug, Clone)]
+pub struct FailedAccount {
+    pub pubkey: Pubkey,
+    pub reason: String,
+}
+
+/// The result of simulating a batch before it is broadcast.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub accounts: Vec<Pubkey>,
+    pub units_consumed: Option<u64>,
+    pub error: Option<String>,
+    pub logs: Vec<String>,
+}
+
+/// What became of a batch after its fee check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchDisposition {
+    /// Not worth its fees; the accounts are left for a later sweep.
+    Deferred,
+    /// Its simulation failed, so it was not broadcast.
+    SimulationFailed,
+    /// Simulated only, because the sweep was a dry run.
+    DryRun,
+    /// Broadcast and landed.
+    Sent,
+    /// Rejected by the cluster, failed on chain or expired before landing.
+    Failed,
+    /// Given up on after an RPC or storage error.
+    Abandoned,
+}
+
+impl BatchDisposition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deferred => "deferred",
+            Self::SimulationFailed => "simulation failed",
+            Self::DryRun => "dry run",
+            Self::Sent => "sent",
+            Self::Failed => "failed",
+            Self::Abandoned => "abandoned",
+        }
+    }
+}
+
+/// The fee-versus-rent check made for a batch before it was simulated, and
+/// what became of the batch afterwards.
+#[derive(Debug, Clone)]
+pub struct FeeDecision {
+    pub accounts: Vec<Pubkey>,
+    pub lamports: u64,
+    pub fee_lamports: u64,
+    pub disposition: BatchDisposition,
+}
+
+impl FeeDecision {
+    pub fn net_lamports(&self) -> i64 {
+        self.lamports as i64 - self.fee_lamports as i64
+    }
+}
+
+/// Per-transaction breakdown of a reclaim run.
+#[derive(Debug, Clone, Default)]
+pub struct ReclaimOutcome {
+    pub batches: Vec<BatchResult>,
+    /// Every simulation run during the sweep, including the failed ones.
+    pub simulations: Vec<Simulation>,
+    /// The profitability check made for every batch.
+    pub decisions: Vec<FeeDecision>,
+    /// Accounts dropped from their batch because their close instruction failed.
+    pub failed: Vec<FailedAccount>,
+    /// Change in the treasury balance across the run, if it was measured.
+    pub treasury_delta: Option<i64>,
+    /// True when the run was cancelled before every batch was handled.
+    pub cancelled: bool,
+    /// Batches abandoned because of an RPC or storage error. The other
+    /// batches are unaffected and still reported.
+    pub errors: Vec<String>,
+}
+
+impl ReclaimOutcome {
+    pub fn total_lamports(&self) -> u64 {
+        self.batches.iter().map(|b| b.lamports).sum()
+    }
+
+    pub fn closed_count(&self) -> u64 {
+        self.batches.iter().map(|b| b.accounts.len() as u64).sum()
+    }
+
+    pub fn total_fees(&self) -> u64 {
+        self.batches.iter().map(|b| b.fee_lamports).sum()
+    }
+
+    fn merge(&mut self, other: ReclaimOutcome) {
+        self.batches.extend(other.batches);
+        self.simulations.extend(other.simulations);
+        self.decisions.extend(other.decisions);
+        self.failed.extend(other.failed);
+        self.errors.extend(other.errors);
+    }
+
+    /// Records what became of the batch attempt decided on last.
+    fn settle(&mut self, disposition: BatchDisposition) {
+        if let Some(decision) = self.decisions.last_mut() {
+            decision.disposition = disposition;
+        }
+    }
+
+    /// Abandons the batch being closed because of `err`.
+    fn abandon(mut self, err: anyhow::Error) -> Self {
+        error!("Batch abandoned: {:#}", err);
+        self.errors.push(format!("{:#}", err));
+        self.settle(BatchDisposition::Abandoned);
+        self
+    }
+}
 
 pub struct Reclaimer {
-    client: RpcClient,
+    client: Arc<dyn LedgerClient>,
     keypair: Keypair,
     treasury: Pubkey,
+    fee_policy: FeePolicy,
+    journal: Option<Arc<Storage>>,
 }
 
 impl Reclaimer {
     pub fn new(rpc_url: &str, keypair: Keypair, treasury: Pubkey) -> Self {
+        Self::with_client(Arc::new(RpcClient::new(rpc_url.to_string())), keypair, treasury)
+    }
+
+    pub fn with_client(client: Arc<dyn LedgerClient>, keypair: Keypair, treasury: Pubkey) -> Self {
         Self {
-            client: RpcClient::new(rpc_url.to_string()),
+            client,
             keypair,
             treasury,
+            fee_policy: FeePolicy::default(),
+            journal: None,
         }
     }
 
-    pub fn reclaim_accounts(&self, accounts: &[Pubkey], dry_run: bool) -> Result<(u64, u64)> {
+    pub fn with_fee_policy(mut self, fee_policy: FeePolicy) -> Self {
+        self.fee_policy = fee_policy;
+        self
+    }
+
+    /// Records every batch in the reclaim journal of `storage` before it is
+    /// broadcast and as it resolves.
+    pub fn with_journal(mut self, storage: Arc<Storage>) -> Self {
+        self.journal = Some(storage);
+        self
+    }
+
+    /// Resolves batches the journal still shows as planned or sent, which
+    /// happens when the process stopped before their outcome was known. Waits
+    /// for any that could still land or are not yet confirmed, so no new
+    /// batch races an old one.
+    /// Returns the batches that turned out to have landed.
+    pub async fn reconcile_journal(&self) -> Result<Vec<JournalEntry>> {
+        let Some(journal) = &self.journal else {
+            return Ok(Vec::new());
+        };
+        let mut recovered = Vec::new();
+        for mut entry in journal.in_flight_batches()? {
+            match self.await_landing(&entry.signature, &entry.blockhash).await? {
+                Landing::Landed { slot, err: None } => {
+                    info!("Journal: batch {} landed in slot {}", entry.signature, slot);
+                    journal.set_journal_status(&entry.signature, JournalStatus::Confirmed, Some(slot))?;
+                    entry.status = JournalStatus::Confirmed;
+                    entry.slot = Some(slot);
+                    recovered.push(entry);
+                }
+                Landing::Landed { slot, err: Some(err) } => {
+                    warn!("Journal: batch {} failed on chain: {}", entry.signature, err);
+                    journal.set_journal_status(&entry.signature, JournalStatus::Failed, Some(slot))?;
+                }
+                Landing::Expired => {
+                    info!("Journal: batch {} never landed", entry.signature);
+                    journal.set_journal_status(&entry.signature, JournalStatus::Failed, None)?;
+                }
+            }
+        }
+        Ok(recovered)
+    }
+
+    /// Where closed accounts' rent is sent.
+    pub fn treasury(&self) -> Pubkey {
+        self.treasury
+    }
+
+    /// The keypair that signs and authorizes the closes.
+    pub fn operator(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    /// Closes `accounts` in batches of up to 20, sending several batches at
+    /// once. Once `cancel` fires no new batch is started and batches still in
+    /// flight are abandoned; what completed so far is returned.
+    pub async fn reclaim_accounts(&self, accounts: &[(Pubkey, Account)], dry_run: bool, cancel: &CancellationToken) -> Result<ReclaimOutcome> {
+        let mut outcome = ReclaimOutcome::default();
         if accounts.is_empty() {
-            return Ok((0, 0));
+            return Ok(outcome);
+        }
+
+        let price = self.compute_unit_price(accounts).await;
+        let treasury_before = if dry_run {
+            info!("Dry run: simulating reclaim of {} accounts", accounts.len());
+            None
+        } else {
+            Some(self.client.get_balance(&self.treasury).await?)
+        };
+
+        // Priority fees reserved by batches so far, shared so the per-sweep cap
+        // holds while batches run concurrently.
+        let spent = Mutex::new(0u64);
+        let batches: Vec<_> = accounts
+            .chunks(20)
+            .map(|chunk| {
+                let spent = &spent;
+                async move {
+                    tokio::select! {
+                        biased;
+                        _ = cancel.cancelled() => None,
+                        result = self.close_batch(chunk.iter().collect(), dry_run, price, spent) => Some(result),
+                    }
+                }
+            })
+            .collect();
+        let mut results = stream::iter(batches).buffer_unordered(MAX_CONCURRENT_BATCHES);
+
+        while let Some(result) = results.next().await {
+            match result {
+                Some(batch_outcome) => outcome.merge(batch_outcome),
+                None => outcome.cancelled = true,
+            }
+        }
+        drop(results);
+
+        if outcome.cancelled {
+            warn!("Reclaim cancelled after {} batches", outcome.batches.len());
         }
 
-        if dry_run {
-            info!("Dry run: would reclaim {} accounts", accounts.len());
-            return Ok((0, accounts.len() as u64));
+        if let Some(treasury_before) = treasury_before {
+            match self.client.get_balance(&self.treasury).await {
+                Ok(treasury_after) => {
+                    let delta = treasury_after as i64 - treasury_before as i64;
+                    outcome.treasury_delta = Some(delta);
+                    self.reconcile(&outcome, delta);
+                }
+                Err(e) => warn!("Could not fetch treasury balance for reconciliation: {}", e),
+            }
         }
 
-        let total_lamports = 0;
-        let mut closed_count = 0;
+        Ok(outcome)
+    }
 
-        // Batch instructions (up to 20 per transaction)
-        for chunk in accounts.chunks(20) {
+    /// Simulates and then sends one batch of closes. A batch is never broadcast
+    /// unless its simulation succeeded. When a single instruction fails, the
+    /// account behind it is dropped and the rest of the batch is retried, so one
+    /// bad account cannot hold back the others until the next interval. An
+    /// error ends this batch only; it is recorded in the outcome so the
+    /// batches that did land are still accounted for.
+    async fn close_batch(&self, mut pending: Vec<&(Pubkey, Account)>, dry_run: bool, price: u64, spent: &Mutex<u64>) -> ReclaimOutcome {
+        let mut outcome = ReclaimOutcome::default();
+        while !pending.is_empty() {
+            let lamports: u64 = pending.iter().map(|(_, a)| a.lamports).sum();
             let mut instructions = Vec::new();
-            for pubkey in chunk {
-                let ix = close_account(
-                    &spl_token::id(),
-                    pubkey,
-                    &self.treasury,
-                    &self.keypair.pubkey(),
-                    &[],
-                )?;
-                instructions.push(ix);
-            }
-
-            let recent_blockhash = self.client.get_latest_blockhash()?;
+            let mut priority_fee = 0;
+            if self.fee_policy.is_enabled() {
+                let units = self.fee_policy.unit_limit(pending.len());
+                let batch_price = {
+                    let mut spent = spent.lock().unwrap();
+                    let batch_price = self.fee_policy.batch_price(price, units, lamports, *spent);
+                    priority_fee = fees::priority_fee(units, batch_price);
+                    *spent += priority_fee;
+                    batch_price
+                };
+                instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(units));
+                if batch_price > 0 {
+                    instructions.push(ComputeBudgetInstruction::set_compute_unit_price(batch_price));
+                }
+            }
+            // Gives back the reservation of an attempt that did not land.
+            let release = || *spent.lock().unwrap() -= priority_fee;
+
+            let fee_lamports = LAMPORTS_PER_SIGNATURE + priority_fee;
+            let profitable = self.fee_policy.is_profitable(lamports, fee_lamports);
+            outcome.decisions.push(FeeDecision {
+                accounts: pending.iter().map(|(p, _)| *p).collect(),
+                lamports,
+                fee_lamports,
+                // Settled below once the batch's fate is known.
+                disposition: if profitable { BatchDisposition::Abandoned } else { BatchDisposition::Deferred },
+            });
+            if !profitable {
+                // The accounts stay open and are picked up again next sweep.
+                info!("Deferring batch of {} accounts: {} lamports recovered for {} in fees", pending.len(), lamports, fee_lamports);
+                release();
+                return outcome;
+            }
+
+            // Close instructions start after the compute budget ones.
+            let first_close = instructions.len();
+            for (pubkey, account) in &pending {
+                match self.close_instruction(pubkey, account) {
+                    Ok(ix) => instructions.push(ix),
+                    Err(e) => {
+                        release();
+                        return outcome.abandon(e);
+                    }
+                }
+            }
+
+            let recent_blockhash = match self.client.get_latest_blockhash().await {
+                Ok(blockhash) => blockhash,
+                Err(e) => {
+                    release();
+                    return outcome.abandon(e.context("Failed to fetch a blockhash"));
+                }
+            };
             let tx = Transaction::new_signed_with_payer(
                 &instructions,
                 Some(&self.keypair.pubkey()),
@@ -56,19 +373,206 @@ impl Reclaimer {
                 recent_blockhash,
             );
 
-            match self.client.send_and_confirm_transaction(&tx) {
-                Ok(sig) => {
-                    info!("Transaction successful: {}", sig);
-                    closed_count += chunk.len() as u64;
-                    // Note: Calculating exact lamports reclaimed from tx receipt would be better
-                    // but for now we assume success means all in chunk were closed.
-                }
+            let sim = match self.client.simulate_transaction(&tx).await {
+                Ok(sim) => sim,
                 Err(e) => {
-                    error!("Transaction failed: {}", e);
+                    release();
+                    return outcome.abandon(e.context("Failed to simulate batch"));
+                }
+            };
+            outcome.simulations.push(Simulation {
+                accounts: pending.iter().map(|(p, _)| *p).collect(),
+                units_consumed: sim.units_consumed,
+                error: sim.err.as_ref().map(|e| e.to_string()),
+                logs: sim.logs.unwrap_or_default(),
+            });
+
+            if let Some(err) = sim.err {
+                release();
+                outcome.settle(BatchDisposition::SimulationFailed);
+                if !Self::drop_failing_account(&err, first_close, &mut pending, &mut outcome) {
+                    error!("Simulation failed, batch not sent: {}", err);
+                    return outcome;
+                }
+                continue;
+            }
+
+            if dry_run {
+                outcome.settle(BatchDisposition::DryRun);
+                outcome.batches.push(BatchResult {
+                    signature: None,
+                    slot: None,
+                    accounts: pending.iter().map(|(p, _)| *p).collect(),
+                    lamports,
+                    fee_lamports,
+                });
+                return outcome;
+            }
+
+            let signature = tx.signatures[0];
+            let accounts: Vec<Pubkey> = pending.iter().map(|(p, _)| *p).collect();
+            if let Some(journal) = &self.journal {
+                // Write-ahead: nothing is broadcast that the journal does not know about.
+                if let Err(e) = journal.journal_batch(&signature, &recent_blockhash, &accounts, lamports, fee_lamports) {
+                    release();
+                    return outcome.abandon(e.context("Failed to journal batch"));
+                }
+            }
+
+            if let Err(e) = self.client.send_transaction(&tx).await {
+                match ledger::transaction_error(&e) {
+                    // Rejected in preflight, so it never reached the cluster.
+                    Some(err) => {
+                        release();
+                        outcome.settle(BatchDisposition::Failed);
+                        self.set_journal_status(&signature, JournalStatus::Failed, None);
+                        if Self::drop_failing_account(&err, first_close, &mut pending, &mut outcome) {
+                            continue;
+                        }
+                        error!("Transaction failed: {}", e);
+                    }
+                    // The transaction may still have been forwarded; the
+                    // journal entry is resolved by the next reconciliation.
+                    None => error!("Failed to send {}: {}", signature, e),
+                }
+                return outcome;
+            }
+            self.set_journal_status(&signature, JournalStatus::Sent, None);
+
+            match self.await_landing(&signature, &recent_blockhash).await {
+                Ok(Landing::Landed { slot, err: None }) => {
+                    info!("Transaction successful: {}", signature);
+                    outcome.settle(BatchDisposition::Sent);
+                    self.set_journal_status(&signature, JournalStatus::Confirmed, Some(slot));
+                    outcome.batches.push(BatchResult {
+                        signature: Some(signature),
+                        slot: Some(slot),
+                        accounts,
+                        lamports,
+                        fee_lamports,
+                    });
+                    return outcome;
+                }
+                Ok(Landing::Landed { slot, err: Some(err) }) => {
+                    outcome.settle(BatchDisposition::Failed);
+                    self.set_journal_status(&signature, JournalStatus::Failed, Some(slot));
+                    if !Self::drop_failing_account(&err, first_close, &mut pending, &mut outcome) {
+                        error!("Transaction {} failed: {}", signature, err);
+                        return outcome;
+                    }
+                }
+                Ok(Landing::Expired) => {
+                    release();
+                    outcome.settle(BatchDisposition::Failed);
+                    self.set_journal_status(&signature, JournalStatus::Failed, None);
+                    error!("Transaction {} expired before landing", signature);
+                    return outcome;
                 }
+                // Left as sent in the journal for the next reconciliation.
+                Err(e) => return outcome.abandon(e.context(format!("Could not confirm {}", signature))),
             }
         }
+        outcome
+    }
 
-        Ok((total_lamports, closed_count))
+    /// Polls `signature` until it is confirmed or `blockhash` expires. A
+    /// status seen only at `processed` is not enough: its fork may still be
+    /// dropped, so polling goes on until it is confirmed or disappears.
+    async fn await_landing(&self, signature: &Signature, blockhash: &Hash) -> Result<Landing> {
+        loop {
+            let status = self.client.get_signature_statuses(&[*signature]).await?.pop().flatten();
+            if let Some(status) = status {
+                if status.satisfies_commitment(CommitmentConfig::confirmed()) {
+                    return Ok(Landing::Landed { slot: status.slot, err: status.err });
+                }
+            } else if !self.client.is_blockhash_valid(blockhash).await?
+                // It may have landed between the two calls.
+                && self.client.get_signature_statuses(&[*signature]).await?.pop().flatten().is_none()
+            {
+                return Ok(Landing::Expired);
+            }
+            tokio::time::sleep(CONFIRM_POLL_INTERVAL).await;
+        }
+    }
+
+    fn set_journal_status(&self, signature: &Signature, status: JournalStatus, slot: Option<u64>) {
+        if let Some(journal) = &self.journal {
+            if let Err(e) = journal.set_journal_status(signature, status, slot) {
+                warn!("Failed to update journal for {}: {}", signature, e);
+            }
+        }
+    }
+
+    /// Removes the account whose close instruction caused `err` from `pending`.
+    /// Returns false if the error cannot be pinned on a single close instruction.
+    fn drop_failing_account(err: &TransactionError, first_close: usize, pending: &mut Vec<&(Pubkey, Account)>, outcome: &mut ReclaimOutcome) -> bool {
+        match err {
+            TransactionError::InstructionError(index, ix_err)
+                if (*index as usize) >= first_close && (*index as usize) - first_close < pending.len() =>
+            {
+                let (pubkey, _) = pending.remove(*index as usize - first_close);
+                warn!("Close of {} failed ({}); retrying the remaining {} accounts", pubkey, ix_err, pending.len());
+                outcome.failed.push(FailedAccount {
+                    pubkey: *pubkey,
+                    reason: ix_err.to_string(),
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The compute unit price for this sweep, in micro-lamports. In automatic
+    /// mode it is the configured percentile of recent prioritization fees paid
+    /// for the accounts we are about to write to.
+    async fn compute_unit_price(&self, accounts: &[(Pubkey, Account)]) -> u64 {
+        if !self.fee_policy.auto_priority_fee {
+            return self.fee_policy.compute_unit_price.unwrap_or(0);
+        }
+
+        // The RPC accepts at most 128 addresses.
+        let mut addresses = vec![self.keypair.pubkey(), self.treasury];
+        addresses.extend(accounts.iter().take(126).map(|(p, _)| *p));
+        match self.client.get_recent_prioritization_fees(&addresses).await {
+            Ok(recent) => {
+                let price = fees::percentile(
+                    recent.into_iter().map(|f| f.prioritization_fee).collect(),
+                    self.fee_policy.priority_fee_percentile,
+                );
+                info!("Using priority fee of {} micro-lamports per CU", price);
+                price
+            }
+            Err(e) => {
+                warn!("Could not fetch recent prioritization fees: {}", e);
+                self.fee_policy.compute_unit_price.unwrap_or(0)
+            }
+        }
+    }
+
+    fn close_instruction(&self, pubkey: &Pubkey, account: &Account) -> Result<Instruction> {
+        // The owning program decides whether this is a legacy SPL Token
+        // or a Token-2022 close.
+        Ok(close_account(
+            &account.owner,
+            pubkey,
+            &self.treasury,
+            &self.keypair.pubkey(),
+            &[],
+        )?)
+    }
+
+    /// Compares the rent we expect to have recovered with what actually arrived
+    /// in the treasury. A mismatch usually means another transfer touched the
+    /// treasury during the run, or the treasury also paid the fees.
+    fn reconcile(&self, outcome: &ReclaimOutcome, delta: i64) {
+        let expected = outcome.total_lamports() as i64;
+        if delta != expected {
+            warn!(
+                "Treasury balance changed by {} lamports, expected {} from {} closed accounts",
+                delta,
+                expected,
+                outcome.closed_count()
+            );
+        }
     }
 }
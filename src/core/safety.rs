@@ -1,30 +1,317 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::account::Account;
+use solana_sdk::program_option::COption;
+use solana_sdk::program_pack::Pack;
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use spl_token_2022::extension::confidential_transfer::ConfidentialTransferAccount;
+use spl_token_2022::extension::confidential_transfer_fee::ConfidentialTransferFeeAmount;
+use spl_token_2022::extension::transfer_fee::TransferFeeAmount;
 
-pub fn is_safe_to_reclaim(pubkey: &Pubkey, account: &Account, whitelist: &[String]) -> bool {
-    if whitelist.contains(&pubkey.to_string()) {
-        return false;
+/// Why an account was not considered safe to close.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectionReason {
+    Whitelisted,
+    ProtectedMint(Pubkey),
+    TooNew { age_secs: u64, min_age_secs: u64 },
+    /// Empty, but not for long enough to rule out a refill.
+    GracePeriod { remaining_secs: u64 },
+    NotTokenAccount,
+    InvalidData,
+    NonZeroBalance(u64),
+    Frozen,
+    NativeAccount,
+    ActiveDelegate(Pubkey),
+    NotOwner(Pubkey),
+    CloseAuthorityMismatch(Pubkey),
+    /// A Token-2022 extension still holds value that blocks the close.
+    ExtensionNotClosable(&'static str),
+    NoRent,
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Whitelisted => write!(f, "whitelisted"),
+            Self::ProtectedMint(mint) => write!(f, "mint {} is protected", mint),
+            Self::TooNew { age_secs, min_age_secs } => {
+                write!(f, "first seen {}h ago, protected for {}h", age_secs / 3600, min_age_secs / 3600)
+            }
+            Self::GracePeriod { remaining_secs } => {
+                write!(f, "in grace period for another {}m", remaining_secs.div_ceil(60))
+            }
+            Self::NotTokenAccount => write!(f, "not owned by a token program"),
+            Self::InvalidData => write!(f, "not an initialized token account"),
+            Self::NonZeroBalance(amount) => write!(f, "token balance is {}", amount),
+            Self::Frozen => write!(f, "account is frozen"),
+            Self::NativeAccount => write!(f, "native (wSOL) account"),
+            Self::ActiveDelegate(delegate) => write!(f, "delegated to {}", delegate),
+            Self::NotOwner(owner) => write!(f, "owned by {}", owner),
+            Self::CloseAuthorityMismatch(authority) => write!(f, "close authority is {}", authority),
+            Self::ExtensionNotClosable(ext) => write!(f, "{} extension is not closable", ext),
+            Self::NoRent => write!(f, "holds no lamports"),
+        }
+    }
+}
+
+/// The fields of an SPL Token or Token-2022 account that the safety rules need.
+#[derive(Debug, Clone)]
+pub struct TokenAccountInfo {
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub is_frozen: bool,
+    pub is_native: bool,
+    pub delegate: Option<Pubkey>,
+    pub close_authority: Option<Pubkey>,
+}
+
+impl TokenAccountInfo {
+    /// Unpacks the account with the state type of the program that owns it.
+    pub fn decode(account: &Account) -> Result<Self, RejectionReason> {
+        if account.owner == spl_token::id() {
+            let state = spl_token::state::Account::unpack(&account.data)
+                .map_err(|_| RejectionReason::InvalidData)?;
+            Ok(Self {
+                mint: state.mint,
+                owner: state.owner,
+                amount: state.amount,
+                is_frozen: state.is_frozen(),
+                is_native: state.is_native(),
+                delegate: option(state.delegate),
+                close_authority: option(state.close_authority),
+            })
+        } else if account.owner == spl_token_2022::id() {
+            let state = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+                .map_err(|_| RejectionReason::InvalidData)?;
+            check_extensions(&state)?;
+            let base = state.base;
+            Ok(Self {
+                mint: base.mint,
+                owner: base.owner,
+                amount: base.amount,
+                is_frozen: base.is_frozen(),
+                is_native: base.is_native(),
+                delegate: option(base.delegate),
+                close_authority: option(base.close_authority),
+            })
+        } else {
+            Err(RejectionReason::NotTokenAccount)
+        }
+    }
+}
+
+/// An operator-managed rule, persisted in storage so it can change without a
+/// config redeploy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Never close this token account.
+    ProtectAccount(Pubkey),
+    /// Never close accounts for this mint.
+    ProtectMint(Pubkey),
+    /// Never close accounts that a koralReef scan first saw less than this
+    /// many days ago. This is not the account's on-chain age, which the RPC
+    /// does not expose; an account that predates the first scan counts from
+    /// that scan.
+    MinAgeDays(u64),
+    /// Always sweep accounts for this mint, overriding the age rule. The
+    /// whitelist and protect rules still win, as do the balance, freeze and
+    /// authority checks.
+    SweepMint(Pubkey),
+}
+
+impl Rule {
+    pub fn parse(kind: &str, value: &str) -> anyhow::Result<Self> {
+        let pubkey = || Pubkey::from_str(value).map_err(|_| anyhow::anyhow!("Invalid pubkey: {}", value));
+        Ok(match kind {
+            "account" => Self::ProtectAccount(pubkey()?),
+            "mint" => Self::ProtectMint(pubkey()?),
+            "min-age-days" => Self::MinAgeDays(value.parse().map_err(|_| anyhow::anyhow!("Invalid day count: {}", value))?),
+            "sweep-mint" => Self::SweepMint(pubkey()?),
+            _ => anyhow::bail!("Unknown rule kind: {}", kind),
+        })
     }
 
-    // Ensure it's a Token account
-    if account.owner != spl_token::id() {
-        return false;
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::ProtectAccount(_) => "account",
+            Self::ProtectMint(_) => "mint",
+            Self::MinAgeDays(_) => "min-age-days",
+            Self::SweepMint(_) => "sweep-mint",
+        }
     }
 
-    // Ensure it's empty
-    if account.data.len() == 165 {
-        let amount_bytes = &account.data[64..72];
-        let amount = u64::from_le_bytes(amount_bytes.try_into().unwrap());
-        if amount != 0 {
-            return false;
+    pub fn value(&self) -> String {
+        match self {
+            Self::ProtectAccount(p) | Self::ProtectMint(p) | Self::SweepMint(p) => p.to_string(),
+            Self::MinAgeDays(days) => days.to_string(),
+        }
+    }
+}
+
+/// Everything the safety rules need to judge an account.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// The keypair that signs closes; it must be able to close the account.
+    pub operator: Pubkey,
+    /// Token accounts from `Settings::whitelist`.
+    pub whitelist: Vec<String>,
+    pub rules: Vec<Rule>,
+    /// Unix timestamp at which each account was first seen by a scan.
+    pub first_seen: HashMap<Pubkey, i64>,
+    /// Unix timestamp since which each account has been continuously empty.
+    pub empty_since: HashMap<Pubkey, i64>,
+    /// How long an account must stay empty before it may be closed.
+    pub grace_period_secs: u64,
+    /// Unix timestamp the policy is evaluated at.
+    pub now: i64,
+}
+
+impl Policy {
+    pub fn new(operator: Pubkey, whitelist: &[String]) -> Self {
+        Self {
+            operator,
+            whitelist: whitelist.to_vec(),
+            rules: Vec::new(),
+            first_seen: HashMap::new(),
+            empty_since: HashMap::new(),
+            grace_period_secs: 0,
+            now: unix_now(),
+        }
+    }
+
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn with_first_seen(mut self, first_seen: HashMap<Pubkey, i64>) -> Self {
+        self.first_seen = first_seen;
+        self
+    }
+
+    pub fn with_grace_period(mut self, grace_period_secs: u64, empty_since: HashMap<Pubkey, i64>) -> Self {
+        self.grace_period_secs = grace_period_secs;
+        self.empty_since = empty_since;
+        self
+    }
+
+    /// Empty accounts are only closed once they have stayed empty for the
+    /// whole grace period, so a user who refills an account minutes later
+    /// does not have to pay its rent again.
+    fn check_grace_period(&self, pubkey: &Pubkey) -> Result<(), RejectionReason> {
+        if self.grace_period_secs == 0 {
+            return Ok(());
+        }
+        // An account that was not empty at the previous scan starts its timer now.
+        let since = self.empty_since.get(pubkey).copied().unwrap_or(self.now);
+        let empty_for = self.now.saturating_sub(since).max(0) as u64;
+        if empty_for < self.grace_period_secs {
+            return Err(RejectionReason::GracePeriod { remaining_secs: self.grace_period_secs - empty_for });
+        }
+        Ok(())
+    }
+
+    /// Applies the whitelist and protect rules, then the age rule unless the
+    /// mint is on the sweep list. An explicit protection always wins over
+    /// `sweep-mint`.
+    fn check_rules(&self, pubkey: &Pubkey, info: &TokenAccountInfo) -> Result<(), RejectionReason> {
+        if self.whitelist.contains(&pubkey.to_string()) {
+            return Err(RejectionReason::Whitelisted);
+        }
+
+        for rule in &self.rules {
+            match rule {
+                Rule::ProtectAccount(account) if account == pubkey => return Err(RejectionReason::Whitelisted),
+                Rule::ProtectMint(mint) if *mint == info.mint => return Err(RejectionReason::ProtectedMint(*mint)),
+                _ => {}
+            }
+        }
+
+        if self.rules.iter().any(|r| matches!(r, Rule::SweepMint(mint) if *mint == info.mint)) {
+            return Ok(());
+        }
+
+        for rule in &self.rules {
+            if let Rule::MinAgeDays(days) = rule {
+                // Accounts we have never seen before are treated as brand new.
+                let first_seen = self.first_seen.get(pubkey).copied().unwrap_or(self.now);
+                let age_secs = self.now.saturating_sub(first_seen).max(0) as u64;
+                let min_age_secs = days * 86_400;
+                if age_secs < min_age_secs {
+                    return Err(RejectionReason::TooNew { age_secs, min_age_secs });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether `account` can be closed by the policy's operator without
+/// losing anything but rent, and without breaking any operator rule.
+pub fn check_reclaimable(pubkey: &Pubkey, account: &Account, policy: &Policy) -> Result<TokenAccountInfo, RejectionReason> {
+    let info = TokenAccountInfo::decode(account)?;
+    policy.check_rules(pubkey, &info)?;
+    let operator = &policy.operator;
+
+    if info.amount != 0 {
+        return Err(RejectionReason::NonZeroBalance(info.amount));
+    }
+    if info.is_frozen {
+        return Err(RejectionReason::Frozen);
+    }
+    if info.is_native {
+        return Err(RejectionReason::NativeAccount);
+    }
+    if let Some(delegate) = info.delegate {
+        return Err(RejectionReason::ActiveDelegate(delegate));
+    }
+    if info.owner != *operator {
+        return Err(RejectionReason::NotOwner(info.owner));
+    }
+    // Without an explicit close authority the owner may close the account.
+    if let Some(authority) = info.close_authority {
+        if authority != *operator {
+            return Err(RejectionReason::CloseAuthorityMismatch(authority));
         }
-    } else {
-        return false;
     }
 
     if account.lamports == 0 {
-        return false;
+        return Err(RejectionReason::NoRent);
     }
 
-    true
+    policy.check_grace_period(pubkey)?;
+
+    Ok(info)
+}
+
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Token-2022 accounts can only be closed once no extension still holds
+/// withheld fees or confidential balances.
+fn check_extensions(state: &StateWithExtensions<spl_token_2022::state::Account>) -> Result<(), RejectionReason> {
+    if let Ok(ext) = state.get_extension::<TransferFeeAmount>() {
+        ext.closable().map_err(|_| RejectionReason::ExtensionNotClosable("TransferFeeAmount"))?;
+    }
+    if let Ok(ext) = state.get_extension::<ConfidentialTransferAccount>() {
+        ext.closable().map_err(|_| RejectionReason::ExtensionNotClosable("ConfidentialTransferAccount"))?;
+    }
+    if let Ok(ext) = state.get_extension::<ConfidentialTransferFeeAmount>() {
+        ext.closable().map_err(|_| RejectionReason::ExtensionNotClosable("ConfidentialTransferFeeAmount"))?;
+    }
+    Ok(())
+}
+
+fn option(value: COption<Pubkey>) -> Option<Pubkey> {
+    match value {
+        COption::Some(v) => Some(v),
+        COption::None => None,
+    }
 }
@@ -1,65 +1,114 @@
-use solana_client::rpc_client::RpcClient;
+use std::sync::Arc;
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::pubkey::Pubkey;
-use solana_client::rpc_filter::{RpcFilterType, Memcmp, MemcmpEncodedBytes};
 use solana_sdk::account::Account;
 use anyhow::Result;
+use crate::core::ledger::LedgerClient;
+use crate::core::safety::{self, Policy, RejectionReason};
+
+/// One token account seen during a scan and the verdict of the safety rules.
+#[derive(Debug, Clone)]
+pub struct ScanEntry {
+    pub pubkey: Pubkey,
+    pub account: Account,
+    /// `None` when the account data could not be decoded.
+    pub mint: Option<Pubkey>,
+    /// Token balance, `None` when the account data could not be decoded.
+    pub amount: Option<u64>,
+    pub verdict: Result<(), RejectionReason>,
+}
+
+impl ScanEntry {
+    pub fn is_reclaimable(&self) -> bool {
+        self.verdict.is_ok()
+    }
+}
+
+/// Every candidate account found by a scan, reclaimable or not.
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    /// Unix timestamp of the scan.
+    pub scanned_at: i64,
+    pub entries: Vec<ScanEntry>,
+}
+
+impl ScanReport {
+    pub fn reclaimable(&self) -> Vec<(Pubkey, Account)> {
+        self.entries
+            .iter()
+            .filter(|e| e.is_reclaimable())
+            .map(|e| (e.pubkey, e.account.clone()))
+            .collect()
+    }
+}
 
 pub struct Scanner {
-    client: RpcClient,
+    client: Arc<dyn LedgerClient>,
 }
 
 impl Scanner {
     pub fn new(rpc_url: &str) -> Self {
-        Self {
-            client: RpcClient::new(rpc_url.to_string()),
-        }
+        Self::with_client(Arc::new(RpcClient::new(rpc_url.to_string())))
+    }
+
+    pub fn with_client(client: Arc<dyn LedgerClient>) -> Self {
+        Self { client }
+    }
+
+    pub async fn find_reclaimable_accounts(&self, policy: &Policy) -> Result<Vec<(Pubkey, Account)>> {
+        Ok(self.scan(policy).await?.reclaimable())
     }
 
-    pub fn find_reclaimable_accounts(&self, owner: &Pubkey, whitelist: &[String]) -> Result<Vec<(Pubkey, Account)>> {
-        let token_program_id = spl_token::id();
-        
-        let filters = vec![
-            RpcFilterType::DataSize(165),
-            RpcFilterType::Memcmp(Memcmp::new(
-                32, 
-                MemcmpEncodedBytes::Base58(owner.to_string()),
-            )),
-        ];
+    /// Fetches every token account held by the policy's operator and records
+    /// why each one is or is not safe to close.
+    pub async fn scan(&self, policy: &Policy) -> Result<ScanReport> {
+        let owner = &policy.operator;
+        let mut accounts = self.get_token_accounts(&spl_token::id(), owner).await?;
+        // Token-2022 mints are weeded out when the data is unpacked.
+        accounts.extend(self.get_token_accounts(&spl_token_2022::id(), owner).await?);
 
+        let scanned_at = policy.now;
+
+        let mut entries = Vec::new();
+        for (pubkey, account) in accounts {
+            let decoded = safety::TokenAccountInfo::decode(&account);
+            // The Token-2022 owner filter can also match mints; they are not candidates.
+            if decoded.is_err() && is_token_2022_mint(&account) {
+                continue;
+            }
+            let mint = decoded.as_ref().ok().map(|info| info.mint);
+            let amount = decoded.as_ref().ok().map(|info| info.amount);
+            let verdict = safety::check_reclaimable(&pubkey, &account, policy).map(|_| ());
+            if let Err(reason) = &verdict {
+                log::debug!("Skipping {}: {}", pubkey, reason);
+            }
+            entries.push(ScanEntry { pubkey, account, mint, amount, verdict });
+        }
+
+        Ok(ScanReport { scanned_at, entries })
+    }
+
+    async fn get_token_accounts(&self, program_id: &Pubkey, owner: &Pubkey) -> Result<Vec<(Pubkey, Account)>> {
         let mut delay = std::time::Duration::from_millis(500);
         let mut attempts = 0;
         let max_attempts = 5;
 
-        let accounts = loop {
-            match self.client.get_program_accounts_with_config(
-                &token_program_id,
-                solana_client::rpc_config::RpcProgramAccountsConfig {
-                    filters: Some(filters.clone()),
-                    account_config: solana_client::rpc_config::RpcAccountInfoConfig {
-                        encoding: Some(solana_account_decoder::UiAccountEncoding::Base64),
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                },
-            ) {
-                Ok(accounts) => break accounts,
+        loop {
+            match self.client.get_token_accounts(program_id, owner).await {
+                Ok(accounts) => return Ok(accounts),
                 Err(e) if attempts < max_attempts => {
                     log::warn!("RPC call failed (attempt {}): {}. Retrying in {:?}...", attempts + 1, e, delay);
-                    std::thread::sleep(delay);
+                    tokio::time::sleep(delay).await;
                     delay *= 2;
                     attempts += 1;
                 }
                 Err(e) => return Err(anyhow::anyhow!("RPC failed after {} attempts: {}", max_attempts, e)),
             }
-        };
-
-        let mut reclaimable = Vec::new();
-        for (pubkey, account) in accounts {
-            if crate::core::safety::is_safe_to_reclaim(&pubkey, &account, whitelist) {
-                reclaimable.push((pubkey, account));
-            }
         }
-
-        Ok(reclaimable)
     }
 }
+
+fn is_token_2022_mint(account: &Account) -> bool {
+    account.owner == spl_token_2022::id()
+        && spl_token_2022::extension::StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&account.data).is_ok()
+}
@@ -0,0 +1,258 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use tokio_util::sync::CancellationToken;
+use crate::config::Settings;
+use crate::core::reclaimer::{ReclaimOutcome, Reclaimer};
+use crate::core::safety::{Policy, TokenAccountInfo};
+use crate::core::scanner::{ScanReport, Scanner};
+use std::collections::HashMap;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use crate::core::safety::unix_now;
+use crate::storage::{JournalEntry, Reclamation, Storage};
+
+/// Everything one scan-and-reclaim pass produced.
+pub struct SweepResult {
+    pub report: ScanReport,
+    pub outcome: ReclaimOutcome,
+    /// Batches from an earlier run that the journal showed in flight and
+    /// that turned out to have landed.
+    pub recovered: Vec<JournalEntry>,
+    pub dry_run: bool,
+}
+
+impl SweepResult {
+    /// Accounts actually closed on chain: this sweep's batches unless it was a
+    /// dry run, plus any recovered from the journal.
+    pub fn closed_count(&self) -> u64 {
+        let swept = if self.dry_run { 0 } else { self.outcome.closed_count() };
+        swept + self.recovered.iter().map(|e| e.accounts.len() as u64).sum::<u64>()
+    }
+
+    /// Lamports actually recovered, counted the same way as `closed_count`.
+    pub fn total_lamports(&self) -> u64 {
+        let swept = if self.dry_run { 0 } else { self.outcome.total_lamports() };
+        swept + self.recovered.iter().map(|e| e.lamports).sum::<u64>()
+    }
+
+    /// One-line summary for the history log and Telegram.
+    pub fn summary(&self) -> String {
+        let outcome = &self.outcome;
+        let count = outcome.closed_count();
+        let lamports = outcome.total_lamports();
+        let summary = if self.dry_run {
+            let failed_sims = outcome.simulations.iter().filter(|sim| sim.error.is_some()).count();
+            let units: u64 = outcome.simulations.iter().filter_map(|sim| sim.units_consumed).sum();
+            format!(
+                "♻️ [DRY RUN] Would reclaim {} accounts ({:.4} SOL). Simulated {} batches: {} failed, {} CU used.",
+                count, lamports as f64 / 1e9, outcome.simulations.len(), failed_sims, units
+            )
+        } else {
+            format!(
+                "♻️ Reclaimed {} accounts ({:.4} SOL, {:.6} SOL in fees).",
+                count, lamports as f64 / 1e9, outcome.total_fees() as f64 / 1e9
+            )
+        };
+        let summary = if self.recovered.is_empty() {
+            summary
+        } else {
+            format!("{} Recovered {} batches from an earlier run.", summary, self.recovered.len())
+        };
+        let summary = if outcome.errors.is_empty() {
+            summary
+        } else {
+            format!("{} {} batches abandoned after errors.", summary, outcome.errors.len())
+        };
+        if outcome.cancelled {
+            format!("{} Cancelled before all batches were sent.", summary)
+        } else {
+            summary
+        }
+    }
+}
+
+/// What a sweep would close right now. Nothing is closed or recorded.
+pub struct SweepPreview {
+    /// Largest rent first.
+    pub candidates: Vec<PreviewCandidate>,
+}
+
+pub struct PreviewCandidate {
+    pub pubkey: Pubkey,
+    pub mint: Option<Pubkey>,
+    pub lamports: u64,
+}
+
+impl SweepPreview {
+    pub fn total_lamports(&self) -> u64 {
+        self.candidates.iter().map(|c| c.lamports).sum()
+    }
+
+    /// The candidate count and total, followed by at most `max_candidates`
+    /// accounts.
+    pub fn render(&self, max_candidates: usize) -> String {
+        let mut out = format!(
+            "🔎 Preview: {} accounts, {:.6} SOL reclaimable\n",
+            self.candidates.len(), self.total_lamports() as f64 / 1e9
+        );
+        for candidate in self.candidates.iter().take(max_candidates) {
+            out.push_str(&format!(
+                "\n{}\n  mint: {}\n  {:.6} SOL\n",
+                candidate.pubkey,
+                candidate.mint.map(|m| m.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                candidate.lamports as f64 / 1e9
+            ));
+        }
+        if self.candidates.len() > max_candidates {
+            out.push_str(&format!("\n… and {} more\n", self.candidates.len() - max_candidates));
+        }
+        out
+    }
+}
+
+/// The safety policy a sweep by `operator` applies.
+pub fn sweep_policy(operator: Pubkey, storage: &Storage, settings: &Settings) -> Result<Policy> {
+    // Rules are re-read every sweep so changes apply without a restart.
+    // Never sweep without them: a missing rule could expose a protected account.
+    let rules = storage.list_rules().context("Failed to load rules")?;
+    Ok(Policy::new(operator, &settings.whitelist)
+        .with_rules(rules)
+        .with_first_seen(storage.first_seen().unwrap_or_default())
+        .with_grace_period(
+            settings.grace_period_minutes.unwrap_or(0) * 60,
+            storage.empty_since().unwrap_or_default(),
+        ))
+}
+
+/// Scans with the same policy as [`run_sweep`] and lists what it would close.
+pub async fn preview_sweep(scanner: &Scanner, operator: Pubkey, storage: &Storage, settings: &Settings) -> Result<SweepPreview> {
+    let policy = sweep_policy(operator, storage, settings)?;
+    let accounts = scanner.find_reclaimable_accounts(&policy).await.context("Scanner error")?;
+    let mut candidates: Vec<PreviewCandidate> = accounts.into_iter()
+        .map(|(pubkey, account)| PreviewCandidate {
+            pubkey,
+            mint: TokenAccountInfo::decode(&account).ok().map(|info| info.mint),
+            lamports: account.lamports,
+        })
+        .collect();
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.lamports));
+    Ok(SweepPreview { candidates })
+}
+
+/// Scans the operator's token accounts, closes the reclaimable ones and
+/// records the report, simulations and decisions in storage. Batches left in
+/// flight by an earlier run are reconciled first. When `cancel`
+/// fires during the scan nothing is closed; during the reclaim, batches
+/// already confirmed are still recorded.
+pub async fn run_sweep(scanner: &Scanner, reclaimer: &Reclaimer, storage: &Storage, settings: &Settings, cancel: &CancellationToken) -> Result<SweepResult> {
+    let recovered = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => bail!("Sweep cancelled during journal reconciliation"),
+        recovered = reclaimer.reconcile_journal() => recovered.context("Failed to reconcile reclaim journal")?,
+    };
+    for entry in &recovered {
+        let _ = storage.log_event(&format!(
+            "✅ Recovered {} closed accounts ({:.4} SOL) in {}",
+            entry.accounts.len(), entry.lamports as f64 / 1e9, entry.signature
+        ));
+        // The journal keeps no per-account details, so they come from the
+        // last scan that saw each account.
+        let known: HashMap<Pubkey, (Option<Pubkey>, u64)> = entry.accounts.iter()
+            .filter_map(|p| storage.last_scanned(p).ok().flatten().map(|details| (*p, details)))
+            .collect();
+        record_reclamations(storage, &entry.accounts, &known, entry.signature, entry.slot, entry.fee_lamports, reclaimer.treasury());
+    }
+
+    let policy = sweep_policy(reclaimer.operator(), storage, settings)?;
+
+    let report = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => bail!("Sweep cancelled during scan"),
+        report = scanner.scan(&policy) => report.context("Scanner error")?,
+    };
+    if let Err(e) = storage.save_scan_report(&report) {
+        warn!("Failed to save scan report: {}", e);
+    }
+
+    let outcome = reclaimer
+        .reclaim_accounts(&report.reclaimable(), settings.dry_run, cancel)
+        .await
+        .context("Reclaim error")?;
+    record_outcome(storage, &outcome);
+    let known: HashMap<Pubkey, (Option<Pubkey>, u64)> = report.entries.iter()
+        .map(|e| (e.pubkey, (e.mint, e.account.lamports)))
+        .collect();
+    for batch in &outcome.batches {
+        if let Some(signature) = batch.signature {
+            record_reclamations(storage, &batch.accounts, &known, signature, batch.slot, batch.fee_lamports, reclaimer.treasury());
+        }
+    }
+
+    Ok(SweepResult { report, outcome, recovered, dry_run: settings.dry_run })
+}
+
+fn record_outcome(storage: &Storage, outcome: &ReclaimOutcome) {
+    for err in &outcome.errors {
+        let _ = storage.log_event(&format!("❌ Batch abandoned: {}", err));
+    }
+    for batch in &outcome.batches {
+        if let Some(sig) = &batch.signature {
+            let _ = storage.log_event(&format!(
+                "✅ Closed {} accounts ({:.4} SOL) in {}",
+                batch.accounts.len(), batch.lamports as f64 / 1e9, sig
+            ));
+        }
+    }
+    for decision in &outcome.decisions {
+        let _ = storage.log_event(&format!(
+            "💰 Batch of {} accounts: {} lamports rent, {} lamports fees, net {} → {}",
+            decision.accounts.len(), decision.lamports, decision.fee_lamports, decision.net_lamports(),
+            decision.disposition.as_str()
+        ));
+    }
+    for sim in &outcome.simulations {
+        if let Err(e) = storage.record_simulation(sim) {
+            warn!("Failed to record simulation: {}", e);
+        }
+    }
+    for failed in &outcome.failed {
+        let _ = storage.log_event(&format!("⚠️ Skipped {}: {}", failed.pubkey, failed.reason));
+    }
+}
+
+/// Adds one reclamations row per account of a landed batch. The batch fee is
+/// split evenly, with any remainder charged to the first account.
+fn record_reclamations(
+    storage: &Storage,
+    accounts: &[Pubkey],
+    known: &HashMap<Pubkey, (Option<Pubkey>, u64)>,
+    signature: Signature,
+    slot: Option<u64>,
+    fee_lamports: u64,
+    treasury: Pubkey,
+) {
+    if accounts.is_empty() {
+        return;
+    }
+    let share = fee_lamports / accounts.len() as u64;
+    let remainder = fee_lamports % accounts.len() as u64;
+    let reclaimed_at = unix_now();
+    let rows: Vec<Reclamation> = accounts.iter().enumerate()
+        .map(|(i, pubkey)| {
+            let (mint, lamports) = known.get(pubkey).copied().unwrap_or((None, 0));
+            Reclamation {
+                pubkey: *pubkey,
+                mint,
+                lamports,
+                signature,
+                slot,
+                fee_lamports: if i == 0 { share + remainder } else { share },
+                treasury,
+                reclaimed_at,
+            }
+        })
+        .collect();
+    if let Err(e) = storage.record_reclamations(&rows) {
+        warn!("Failed to record reclamations for {}: {}", signature, e);
+    }
+}
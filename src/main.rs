@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::time::{sleep, Duration};
@@ -6,15 +7,20 @@ use koralreef::config::{Config, AppMode};
 use koralreef::state::{AppState, SharedState};
 use koralreef::core::scanner::Scanner;
 use koralreef::core::reclaimer::Reclaimer;
+use koralreef::core::fees::FeePolicy;
+use koralreef::core::safety::{unix_now, Rule};
+use koralreef::core::sweep::run_sweep;
 use koralreef::bot;
-use koralreef::storage::Storage;
-use solana_sdk::signature::{Keypair, Signer};
+use koralreef::storage::backup;
+use koralreef::storage::keys::{PassphraseSource, BACKUP_PASSPHRASE_ENV, NEW_PASSPHRASE_ENV, PASSPHRASE_ENV};
+use koralreef::storage::{parse_duration, Pause, Storage};
+use zeroize::Zeroize;
 use solana_sdk::pubkey::Pubkey;
 use teloxide::requests::Requester;
 use anyhow::Context;
 use std::str::FromStr;
 use log::{info, error, warn};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -42,15 +48,131 @@ struct Args {
     /// Lock the bot in demo mode (cannot be switched via Telegram)
     #[arg(long)]
     demo_only: bool,
+
+    /// Read the database passphrase from this file descriptor instead of
+    /// KORALREEF_PASSPHRASE or a prompt
+    #[arg(long, value_name = "FD")]
+    passphrase_fd: Option<i32>,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Print the most recent scan report and exit
+    Report,
+    /// Derive the master key from a passphrase instead of the .key file, or
+    /// change the current passphrase. The new passphrase is read from
+    /// --new-passphrase-fd, KORALREEF_NEW_PASSPHRASE or a prompt
+    SetPassphrase {
+        #[arg(long, value_name = "FD")]
+        new_passphrase_fd: Option<i32>,
+    },
+    /// Replace the master key and the key file (or the passphrase salt) and
+    /// re-encrypt every stored secret; the old key file decrypts nothing after
+    RotateKey,
+    /// Write a passphrase-encrypted archive of the database and key file.
+    /// The archive passphrase is read from --backup-passphrase-fd,
+    /// KORALREEF_BACKUP_PASSPHRASE or a prompt
+    Backup {
+        archive: PathBuf,
+        #[arg(long, value_name = "FD")]
+        backup_passphrase_fd: Option<i32>,
+    },
+    /// Restore an archive written by `backup` into ~/.koralReef
+    Restore {
+        archive: PathBuf,
+        /// Replace an existing database
+        #[arg(long)]
+        force: bool,
+        #[arg(long, value_name = "FD")]
+        backup_passphrase_fd: Option<i32>,
+    },
+    /// Stop scheduled sweeps without stopping the daemon, for DURATION
+    /// (e.g. 30m, 2h, 1d) or until `resume`
+    Pause {
+        duration: Option<String>,
+    },
+    /// Restart scheduled sweeps
+    Resume,
+    /// Manage whitelist and sweep rules
+    Rules {
+        #[command(subcommand)]
+        action: RuleAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum RuleAction {
+    /// Add a rule. KIND is one of: account, mint, min-age-days, sweep-mint.
+    /// min-age-days counts from the first scan that saw an account
+    Add { kind: String, value: String },
+    /// Remove a rule
+    Remove { kind: String, value: String },
+    /// List all rules
+    List,
+}
+
+/// Where this invocation's passphrases come from.
+struct Passphrases {
+    database: PassphraseSource,
+    /// The new passphrase of `set-passphrase`, or the archive passphrase of
+    /// `backup` and `restore`.
+    command: Option<PassphraseSource>,
+}
+
+impl Passphrases {
+    /// Reads passphrases given in the environment and removes them from it.
+    /// Must run before the runtime starts any threads.
+    fn take(args: &Args) -> anyhow::Result<Self> {
+        let command = match &args.command {
+            Some(Commands::SetPassphrase { new_passphrase_fd }) => {
+                Some(PassphraseSource::take_from_env(*new_passphrase_fd, NEW_PASSPHRASE_ENV))
+            }
+            Some(Commands::Backup { backup_passphrase_fd, .. } | Commands::Restore { backup_passphrase_fd, .. }) => {
+                Some(PassphraseSource::take_from_env(*backup_passphrase_fd, BACKUP_PASSPHRASE_ENV))
+            }
+            _ => None,
+        };
+        let mut database = PassphraseSource::take_from_env(args.passphrase_fd, PASSPHRASE_ENV);
+        if let (Some(Commands::RotateKey), PassphraseSource::Fd(_)) = (&args.command, &database) {
+            // Rotation reads the passphrase again, and a descriptor can only be read once.
+            database = PassphraseSource::Given(database.read("")?);
+        }
+        Ok(Self { database, command })
+    }
+
+    fn command(&self) -> &PassphraseSource {
+        self.command.as_ref().expect("command passphrase is resolved for commands that need one")
+    }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     env_logger::init();
     let args = Args::parse();
+    let passphrases = Passphrases::take(&args)?;
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(args, passphrases))
+}
+
+async fn run(args: Args, passphrases: Passphrases) -> anyhow::Result<()> {
     info!("Starting kora-reclaim-rs...");
 
-    let storage = Arc::new(Storage::init()?);
+    // Restoring must not go through `Storage::init`, which would create a
+    // fresh key and database in the way of the archive.
+    if let Some(Commands::Restore { archive, force, .. }) = &args.command {
+        let mut passphrase = passphrases.command().read("Backup passphrase: ")?;
+        let result = backup::restore(archive, &passphrase, &Storage::default_dir()?, *force);
+        passphrase.zeroize();
+        let summary = result?;
+        println!("Restored {} ({} bytes).", summary.files.join(", "), summary.bytes);
+        return Ok(());
+    }
+
+    let mut storage = Storage::init(&passphrases.database)?;
     let cancel_token = CancellationToken::new();
 
     if let Some(path) = args.import_key {
@@ -60,6 +182,13 @@ async fn main() -> anyhow::Result<()> {
         info!("Solana keypair imported and encrypted successfully.");
     }
 
+    if let Some(command) = args.command {
+        return run_command(command, &mut storage, &passphrases);
+    }
+    // Held until exit, so key changes wait for the daemon to stop.
+    let _daemon_lock = storage.lock_daemon()?;
+    let storage = Arc::new(storage);
+
     let mut config = if let Some(path) = args.config {
         Config::load(path).unwrap_or_else(|_| Config::demo())
     } else if let Some(mode_str) = &args.mode {
@@ -86,11 +215,21 @@ async fn main() -> anyhow::Result<()> {
         config.settings.dry_run = true;
     }
 
+    match storage.seed_users(&config.telegram.authorized_user_ids) {
+        Ok(0) => {}
+        Ok(added) => info!("Registered {} configured user(s) as owners.", added),
+        Err(e) => warn!("Failed to register configured users: {}", e),
+    }
+
     if config.telegram.bot_token.is_empty() {
         warn!("Telegram Bot Token is missing. Bot will not start.");
     }
 
     let mut initial_state = AppState::new(config.mode);
+    match storage.lifetime_stats() {
+        Ok(stats) => initial_state.restore(stats),
+        Err(e) => warn!("Failed to load persisted stats: {}", e),
+    }
     if args.demo_only || config.settings.demo_only.unwrap_or(false) {
         initial_state.mode = AppMode::Demo;
         initial_state.demo_only = true;
@@ -135,6 +274,82 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn run_command(command: Commands, storage: &mut Storage, passphrases: &Passphrases) -> anyhow::Result<()> {
+    match command {
+        Commands::SetPassphrase { .. } => {
+            let mut passphrase = passphrases.command().read_new()?;
+            let result = storage.set_passphrase(&passphrase);
+            passphrase.zeroize();
+            result?;
+            println!("Master key is now derived from the passphrase; the .key file has been removed.");
+        }
+        Commands::RotateKey => {
+            // A passphrase-protected root key is re-derived under a new salt.
+            let mut passphrase = if storage.is_passphrase_protected()? {
+                Some(passphrases.database.read("Passphrase: ")?)
+            } else {
+                None
+            };
+            let result = storage.rotate_key(passphrase.as_deref());
+            passphrase.zeroize();
+            let (version, count) = result?;
+            println!("Rotated to key version {}; re-encrypted {} settings.", version, count);
+        }
+        Commands::Backup { archive, .. } => {
+            let mut passphrase = passphrases.command().read_new()?;
+            let result = storage.backup(&archive, &passphrase);
+            passphrase.zeroize();
+            let summary = result?;
+            println!("Backed up {} ({} bytes) to {}.", summary.files.join(", "), summary.bytes, archive.display());
+        }
+        Commands::Restore { .. } => unreachable!("restore is handled before storage is opened"),
+        Commands::Pause { duration } => {
+            let now = unix_now();
+            let until = duration.as_deref().map(parse_duration).transpose()?.map(|secs| now + secs as i64);
+            let pause = Pause { paused_at: now, until, by: "cli".to_string() };
+            storage.set_pause(&pause)?;
+            storage.log_event(&format!("⏸️ Scheduled sweeps {} (by cli)", pause.describe(now)))?;
+            println!("Scheduled sweeps {}.", pause.describe(now));
+        }
+        Commands::Resume => {
+            if storage.resume()? {
+                storage.log_event("▶️ Scheduled sweeps resumed (by cli)")?;
+                println!("Scheduled sweeps resumed.");
+            } else {
+                println!("Scheduled sweeps were not paused.");
+            }
+        }
+        Commands::Report => match storage.latest_scan_report()? {
+            Some(report) => println!("{}", report.render(usize::MAX)),
+            None => println!("No scan has been recorded yet."),
+        },
+        Commands::Rules { action } => match action {
+            RuleAction::Add { kind, value } => {
+                let rule = Rule::parse(&kind, &value)?;
+                if storage.add_rule(&rule)? {
+                    println!("Added {} rule: {}", rule.kind(), rule.value());
+                } else {
+                    println!("Rule already exists.");
+                }
+            }
+            RuleAction::Remove { kind, value } => {
+                let rule = Rule::parse(&kind, &value)?;
+                if storage.remove_rule(&rule)? {
+                    println!("Removed {} rule: {}", rule.kind(), rule.value());
+                } else {
+                    println!("No such rule.");
+                }
+            }
+            RuleAction::List => {
+                for rule in storage.list_rules()? {
+                    println!("{:<13} {}", rule.kind(), rule.value());
+                }
+            }
+        },
+    }
+    Ok(())
+}
+
 fn load_config_from_storage(storage: &Storage) -> anyhow::Result<Config> {
     let token = storage.get_setting("bot_token")?.unwrap_or_default();
     let mut config = Config::demo();
@@ -172,37 +387,19 @@ async fn sentinel_loop(
                             if s.mode != AppMode::Demo { continue; }
                             if s.force_run { force = true; s.force_run = false; }
                         }
-                        if force || should_scan(&state, config.settings.scan_interval_hours).await {
+                        if force || (!sweeps_paused(&bot, &storage).await && should_scan(&state, config.settings.scan_interval_hours).await) {
                             let msg = "♻️ [DEMO] Simulated reclaim of 2 accounts (0.004 SOL).";
                             let _ = storage.log_event(msg);
-                            if let (Some(b), Some(admin_id)) = (&bot, storage.get_admin().unwrap_or(None)) {
-                                let _ = b.send_message(teloxide::types::ChatId(admin_id as i64), msg).await;
-                            }
-                            let mut s = state.lock().await;
-                            s.last_scan_time = Some(std::time::Instant::now());
-                            s.last_reclaim_summary = Some(msg.to_string());
+                            notify_owners(&bot, &storage, msg).await;
+                            record_scan(&state, &storage, 0, 0).await;
+                            state.lock().await.last_reclaim_summary = Some(msg.to_string());
                         }
                     }
                 }
             }
             AppMode::Real => {
                 let scanner = Scanner::new(&config.solana.rpc_url);
-                let keypair_res = if let Some(key_json) = storage.get_keypair()? {
-                    let keypair_vec: Vec<u8> = serde_json::from_str(&key_json)?;
-                    Ok(Keypair::from_bytes(&keypair_vec)?)
-                } else if !config.solana.keypair_path.is_empty() {
-                    match std::fs::read_to_string(&config.solana.keypair_path) {
-                        Ok(keypair_bytes) => {
-                            let keypair_vec: Vec<u8> = serde_json::from_str(&keypair_bytes)?;
-                            Ok(Keypair::from_bytes(&keypair_vec)?)
-                        }
-                        Err(e) => Err(anyhow::anyhow!("Failed to read keypair file at {}: {}", config.solana.keypair_path, e)),
-                    }
-                } else {
-                    Err(anyhow::anyhow!("No keypair found in database or config file"))
-                };
-
-                let keypair = match keypair_res {
+                let keypair = match storage.operator_keypair(&config.solana.keypair_path) {
                     Ok(k) => k,
                     Err(e) => {
                         error!("Real mode initialization failed: {}", e);
@@ -214,9 +411,10 @@ async fn sentinel_loop(
                     }
                 };
 
-                let keypair_pubkey = keypair.pubkey();
                 let treasury = Pubkey::from_str(&config.solana.treasury_address)?;
-                let reclaimer = Reclaimer::new(&config.solana.rpc_url, keypair, treasury);
+                let reclaimer = Reclaimer::new(&config.solana.rpc_url, keypair, treasury)
+                    .with_fee_policy(FeePolicy::from_settings(&config.settings))
+                    .with_journal(storage.clone());
 
                 tokio::select! {
                     _ = cancel_token.cancelled() => return Ok(()),
@@ -227,27 +425,16 @@ async fn sentinel_loop(
                             if s.mode != AppMode::Real { continue; }
                             if s.force_run { force = true; s.force_run = false; }
                         }
-                        if force || should_scan(&state, config.settings.scan_interval_hours).await {
-                            match scanner.find_reclaimable_accounts(&keypair_pubkey, &config.settings.whitelist) {
-                                Ok(accounts) => {
-                                    let pubkeys: Vec<Pubkey> = accounts.iter().map(|(p, _)| *p).collect();
-                                    match reclaimer.reclaim_accounts(&pubkeys, config.settings.dry_run) {
-                                        Ok((lamports, count)) => {
-                                            let mut s = state.lock().await;
-                                            s.total_reclaimed_lamports += lamports;
-                                            s.total_accounts_closed += count;
-                                            s.last_scan_time = Some(std::time::Instant::now());
-                                            let summary = format!("♻️ Reclaimed {} accounts ({:.4} SOL).", count, lamports as f64 / 1e9);
-                                            s.last_reclaim_summary = Some(summary.clone());
-                                            let _ = storage.log_event(&summary);
-                                            if let (Some(b), Some(admin_id)) = (&bot, storage.get_admin().unwrap_or(None)) {
-                                                let _ = b.send_message(teloxide::types::ChatId(admin_id as i64), summary).await;
-                                            }
-                                        }
-                                        Err(e) => { let _ = storage.log_event(&format!("❌ Reclaim error: {}", e)); }
-                                    }
+                        if force || (!sweeps_paused(&bot, &storage).await && should_scan(&state, config.settings.scan_interval_hours).await) {
+                            match run_sweep(&scanner, &reclaimer, &storage, &config.settings, &cancel_token).await {
+                                Ok(result) => {
+                                    let summary = result.summary();
+                                    record_scan(&state, &storage, result.total_lamports(), result.closed_count()).await;
+                                    state.lock().await.last_reclaim_summary = Some(summary.clone());
+                                    let _ = storage.log_event(&summary);
+                                    notify_owners(&bot, &storage, &summary).await;
                                 }
-                                Err(e) => { let _ = storage.log_event(&format!("❌ Scanner error: {}", e)); }
+                                Err(e) => { let _ = storage.log_event(&format!("❌ {:#}", e)); }
                             }
                         }
                     }
@@ -257,10 +444,54 @@ async fn sentinel_loop(
     }
 }
 
+/// Whether scheduled sweeps are paused. A timed pause that has run out is
+/// cleared here. If the pause cannot be read, sweeps stay paused rather than
+/// risk moving funds against the operator's wishes.
+async fn sweeps_paused(bot: &Option<teloxide::prelude::Bot>, storage: &Storage) -> bool {
+    match storage.pause() {
+        Ok(Some(pause)) if pause.has_ended(unix_now()) => {
+            if let Err(e) = storage.resume() {
+                warn!("Failed to clear an expired pause: {}", e);
+                return true;
+            }
+            let msg = "▶️ Pause ended; scheduled sweeps resumed.";
+            let _ = storage.log_event(msg);
+            notify_owners(bot, storage, msg).await;
+            false
+        }
+        Ok(Some(_)) => true,
+        Ok(None) => false,
+        Err(e) => {
+            warn!("Failed to read the pause state: {}", e);
+            true
+        }
+    }
+}
+
 async fn should_scan(state: &SharedState, interval_hours: u64) -> bool {
     let s = state.lock().await;
-    match s.last_scan_time {
+    match s.secs_since_last_scan() {
         None => true,
-        Some(last) => last.elapsed() >= Duration::from_secs(interval_hours * 3600),
+        Some(elapsed) => elapsed >= interval_hours * 3600,
     }
-}
\ No newline at end of file
+}
+
+/// Persists a completed scan and updates the in-memory stats.
+async fn record_scan(state: &SharedState, storage: &Storage, reclaimed_lamports: u64, accounts_closed: u64) {
+    let stats = match storage.record_scan(unix_now(), reclaimed_lamports, accounts_closed) {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            warn!("Failed to persist stats: {}", e);
+            None
+        }
+    };
+    state.lock().await.record_scan(reclaimed_lamports, accounts_closed, stats);
+}
+
+/// Sends `text` to every owner. Delivery failures are ignored.
+async fn notify_owners(bot: &Option<teloxide::prelude::Bot>, storage: &Storage, text: &str) {
+    let Some(bot) = bot else { return };
+    for owner in storage.owners().unwrap_or_default() {
+        let _ = bot.send_message(teloxide::types::ChatId(owner as i64), text).await;
+    }
+}
@@ -1,17 +1,29 @@
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use std::time::Instant;
+use crate::bot::confirm::Confirmations;
 use crate::config::AppMode;
+use crate::core::safety::unix_now;
+use crate::storage::LifetimeStats;
 
 pub struct AppState {
+    /// Reclaimed since this process started.
     pub total_reclaimed_lamports: u64,
     pub total_accounts_closed: u64,
+    /// Reclaimed over the lifetime of the database, restored on startup.
+    pub lifetime_reclaimed_lamports: u64,
+    pub lifetime_accounts_closed: u64,
     pub start_time: Instant,
     pub force_run: bool,
     pub mode: AppMode,
     pub demo_only: bool,
-    pub last_scan_time: Option<Instant>,
+    /// Unix timestamp of the last completed scan, restored on startup.
+    pub last_scan_time: Option<i64>,
     pub last_reclaim_summary: Option<String>,
+    /// Destructive bot commands waiting for a tap on Confirm.
+    pub confirmations: Confirmations,
+    /// A `/preview` scan is in progress.
+    pub preview_running: bool,
 }
 
 impl AppState {
@@ -19,14 +31,46 @@ impl AppState {
         Self {
             total_reclaimed_lamports: 0,
             total_accounts_closed: 0,
+            lifetime_reclaimed_lamports: 0,
+            lifetime_accounts_closed: 0,
             start_time: Instant::now(),
             force_run: false,
             mode,
             demo_only: false,
             last_scan_time: None,
             last_reclaim_summary: None,
+            confirmations: Confirmations::default(),
+            preview_running: false,
         }
     }
+
+    /// Loads the persisted lifetime totals and last scan time.
+    pub fn restore(&mut self, stats: LifetimeStats) {
+        self.lifetime_reclaimed_lamports = stats.reclaimed_lamports;
+        self.lifetime_accounts_closed = stats.accounts_closed;
+        self.last_scan_time = stats.last_scan_at;
+    }
+
+    /// Applies a completed scan. `stats` is what storage returned after
+    /// recording it; when that failed, the in-memory lifetime totals are
+    /// advanced instead.
+    pub fn record_scan(&mut self, reclaimed_lamports: u64, accounts_closed: u64, stats: Option<LifetimeStats>) {
+        self.total_reclaimed_lamports += reclaimed_lamports;
+        self.total_accounts_closed += accounts_closed;
+        match stats {
+            Some(stats) => self.restore(stats),
+            None => {
+                self.lifetime_reclaimed_lamports += reclaimed_lamports;
+                self.lifetime_accounts_closed += accounts_closed;
+                self.last_scan_time = Some(unix_now());
+            }
+        }
+    }
+
+    /// Seconds since the last completed scan, if there was one.
+    pub fn secs_since_last_scan(&self) -> Option<u64> {
+        self.last_scan_time.map(|t| (unix_now() - t).max(0) as u64)
+    }
 }
 
 pub type SharedState = Arc<Mutex<AppState>>;
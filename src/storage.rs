@@ -1,119 +1,629 @@
-use std::path::PathBuf;
-use rusqlite::Connection;
+pub mod backup;
+pub mod keys;
+pub mod migrations;
+
+use std::path::{Path, PathBuf};
+use rusqlite::{Connection, OptionalExtension};
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, KeyInit, Payload},
     Aes256Gcm, Nonce,
 };
 use rand::{RngCore, thread_rng};
 use std::fs;
-use anyhow::{Result, Context};
+use anyhow::{bail, Result, Context};
 use zeroize::Zeroize;
+use log::warn;
+use keys::{KdfParams, PassphraseSource, PASSPHRASE_ENV};
+use crate::core::reclaimer::Simulation;
+use crate::core::scanner::ScanReport;
+use crate::core::safety::Rule;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A persisted scan report, as shown by `koralreef report` and `/report`.
+pub struct StoredScanReport {
+    pub timestamp: String,
+    pub total: u64,
+    pub reclaimable: u64,
+    pub entries: Vec<ScanReportRow>,
+}
+
+pub struct ScanReportRow {
+    pub pubkey: String,
+    pub mint: Option<String>,
+    pub lamports: u64,
+    /// Time between the first scan that saw the account and this report.
+    pub age_secs: u64,
+    /// "reclaimable", or the safety rule that excluded the account.
+    pub status: String,
+}
+
+impl StoredScanReport {
+    /// Renders the report as plain text, listing at most `max_entries` accounts.
+    pub fn render(&self, max_entries: usize) -> String {
+        let mut out = format!(
+            "Scan at {} UTC: {} accounts, {} reclaimable\n",
+            self.timestamp, self.total, self.reclaimable
+        );
+        for row in self.entries.iter().take(max_entries) {
+            out.push_str(&format!(
+                "\n{}\n  mint: {}\n  {:.6} SOL, age {}\n  {}\n",
+                row.pubkey,
+                row.mint.as_deref().unwrap_or("unknown"),
+                row.lamports as f64 / 1e9,
+                format_age(row.age_secs),
+                row.status
+            ));
+        }
+        if self.entries.len() > max_entries {
+            out.push_str(&format!("\n… and {} more\n", self.entries.len() - max_entries));
+        }
+        out
+    }
+
+    /// Rent held by the accounts this scan found reclaimable.
+    pub fn reclaimable_lamports(&self) -> u64 {
+        self.entries.iter().filter(|row| row.status == "reclaimable").map(|row| row.lamports).sum()
+    }
+}
+
+/// Fails if `telegram_id` is an owner and no other owner would remain.
+fn ensure_other_owner(conn: &Connection, telegram_id: u64) -> Result<()> {
+    let (is_owner, owners): (bool, u32) = conn.query_row(
+        "SELECT COALESCE(SUM(telegram_id = ?1), 0) > 0, COUNT(*) FROM users WHERE role = 'owner'",
+        [telegram_id as i64],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    if is_owner && owners == 1 {
+        bail!("User {} is the last owner; make someone else an owner first", telegram_id);
+    }
+    Ok(())
+}
+
+/// Parses a duration such as `90s`, `30m`, `2h`, `1d` or `1h30m` into seconds.
+pub fn parse_duration(text: &str) -> Result<u64> {
+    let invalid = || anyhow::anyhow!("Invalid duration '{}'; use e.g. 30m, 2h or 1d", text);
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        total = value.checked_mul(unit).and_then(|secs| total.checked_add(secs)).ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+pub(crate) fn format_age(secs: u64) -> String {
+    match secs {
+        0..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d {}h", secs / 86400, (secs % 86400) / 3600),
+    }
+}
+
+/// Where a close batch is in its life. Rows are written as `Planned` before
+/// broadcast, so a crash at any point leaves a signature to reconcile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalStatus {
+    Planned,
+    Sent,
+    Confirmed,
+    Failed,
+}
+
+impl JournalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalStatus::Planned => "planned",
+            JournalStatus::Sent => "sent",
+            JournalStatus::Confirmed => "confirmed",
+            JournalStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(status: &str) -> Result<Self> {
+        match status {
+            "planned" => Ok(JournalStatus::Planned),
+            "sent" => Ok(JournalStatus::Sent),
+            "confirmed" => Ok(JournalStatus::Confirmed),
+            "failed" => Ok(JournalStatus::Failed),
+            other => anyhow::bail!("Unknown journal status '{}'", other),
+        }
+    }
+}
+
+/// What a Telegram user may do. Each role includes the ones before it:
+/// viewers read stats and logs, operators can also sweep, and owners can
+/// also change the mode, settings and users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Viewer,
+    Operator,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Result<Self> {
+        match role {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "owner" => Ok(Role::Owner),
+            other => anyhow::bail!("Unknown role '{}'; expected viewer, operator or owner", other),
+        }
+    }
+}
+
+/// A Telegram user allowed to talk to the bot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub telegram_id: u64,
+    pub role: Role,
+}
+
+/// One close batch recorded in the reclaim journal.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub signature: Signature,
+    /// Blockhash the transaction was signed with; once it expires an unseen
+    /// signature can never land.
+    pub blockhash: Hash,
+    pub accounts: Vec<Pubkey>,
+    pub lamports: u64,
+    pub fee_lamports: u64,
+    pub status: JournalStatus,
+    pub slot: Option<u64>,
+}
+
+/// One closed account in the `reclamations` ledger.
+#[derive(Debug, Clone)]
+pub struct Reclamation {
+    pub pubkey: Pubkey,
+    /// `None` when the account was never decoded by a scan.
+    pub mint: Option<Pubkey>,
+    pub lamports: u64,
+    pub signature: Signature,
+    pub slot: Option<u64>,
+    /// This account's share of the batch fee.
+    pub fee_lamports: u64,
+    pub treasury: Pubkey,
+    /// Unix timestamp at which the close was recorded.
+    pub reclaimed_at: i64,
+}
+
+/// Reclamations summed over one day, mint or treasury.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReclamationTotals {
+    /// The day (`YYYY-MM-DD`, UTC), mint or treasury the totals are for.
+    pub key: String,
+    pub accounts: u64,
+    pub lamports: u64,
+    pub fee_lamports: u64,
+}
+
+impl ReclamationTotals {
+    pub fn net_lamports(&self) -> i64 {
+        self.lamports as i64 - self.fee_lamports as i64
+    }
+}
+
+/// Counters that survive restarts, kept in the single-row `app_stats` table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LifetimeStats {
+    pub reclaimed_lamports: u64,
+    pub accounts_closed: u64,
+    /// Unix timestamp of the last completed scan.
+    pub last_scan_at: Option<i64>,
+}
+
+/// Scheduled sweeps are stopped while a pause is stored in the single-row
+/// `sentinel_pause` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pause {
+    /// Unix timestamp the pause started.
+    pub paused_at: i64,
+    /// Unix timestamp it ends, or `None` until resumed by hand.
+    pub until: Option<i64>,
+    /// Who paused: `cli` or `telegram:<user id>`.
+    pub by: String,
+}
+
+impl Pause {
+    pub fn has_ended(&self, now: i64) -> bool {
+        self.until.is_some_and(|until| until <= now)
+    }
+
+    /// "paused for another 2h" or "paused until resumed".
+    pub fn describe(&self, now: i64) -> String {
+        match self.until {
+            Some(until) => format!("paused for another {}", format_age((until - now).max(0) as u64)),
+            None => "paused until resumed".to_string(),
+        }
+    }
+}
 
 pub struct Storage {
     pub base_dir: PathBuf,
     pub db_path: PathBuf,
-    key: [u8; 32],
+    /// Version 0 is the root key from the `.key` file or passphrase; later
+    /// versions are master keys from `master_keys`, wrapped by the root key.
+    keys: HashMap<u32, [u8; 32]>,
+    /// Version new ciphertexts are written with.
+    current_key: u32,
 }
 
+/// Settings row holding the [`KdfParams`] of a passphrase-derived root key.
+const KDF_SETTING: &str = "master_key_kdf";
+
+/// Settings row recording that encrypted settings use [`SETTING_FORMAT`].
+const FORMAT_SETTING: &str = "settings_format";
+
+/// The new root key written by [`Storage::rotate_key`] before its transaction
+/// commits, and renamed over `.key` once it has.
+const PENDING_KEY_FILE: &str = ".key.new";
+
+/// File the running daemon holds an exclusive lock on.
+const DAEMON_LOCK_FILE: &str = "daemon.lock";
+
+/// Scan reports kept by [`Storage::save_scan_report`]. Older reports and their
+/// entries are deleted; the latest is enough for `report` and for
+/// [`Storage::last_scanned`], which only looks back to the previous sweep.
+const SCAN_REPORTS_KEPT: u32 = 10;
+
+/// Held by the daemon for as long as it runs; see [`Storage::lock_daemon`].
+pub struct DaemonLock {
+    _file: fs::File,
+}
+
+/// Plaintext encrypted into [`KdfParams::check`].
+const KEY_CHECK: &[u8] = b"koralreef master key";
+
 impl Storage {
-    pub fn init() -> Result<Self> {
+    pub fn init(passphrase: &PassphraseSource) -> Result<Self> {
+        Self::open_with(Self::default_dir()?, passphrase)
+    }
+
+    /// `~/.koralReef`, where `init` keeps the key and database.
+    pub fn default_dir() -> Result<PathBuf> {
         let home = std::env::var("HOME").context("HOME env var not set")?;
-        let base_dir = PathBuf::from(home).join(".koralReef");
-        
+        Ok(PathBuf::from(home).join(".koralReef"))
+    }
+
+    /// Opens (or creates) the key and database under `base_dir`, reading the
+    /// passphrase of a protected database from [`PASSPHRASE_ENV`].
+    pub fn open(base_dir: PathBuf) -> Result<Self> {
+        Self::open_with(base_dir, &PassphraseSource::Env(PASSPHRASE_ENV.to_string()))
+    }
+
+    /// Opens (or creates) the database under `base_dir`. The root key is
+    /// derived from the passphrase when the database is passphrase-protected,
+    /// and read from (or written to) the `.key` file otherwise.
+    pub fn open_with(base_dir: PathBuf, passphrase: &PassphraseSource) -> Result<Self> {
         if !base_dir.exists() {
             fs::create_dir_all(&base_dir)?;
         }
 
+        let db_path = base_dir.join("koral.db");
+        let mut conn = Connection::open(&db_path)?;
+        migrations::migrate(&mut conn)?;
+
         let key_path = base_dir.join(".key");
-        let key = if key_path.exists() {
-            let mut k = fs::read(&key_path)?;
-            if k.len() != 32 {
-                anyhow::bail!("Invalid key length");
+        let root = match Self::kdf_params(&conn)? {
+            Some(params) => {
+                let mut secret = passphrase.read("Passphrase: ")?;
+                let key = params.derive_key(&secret);
+                secret.zeroize();
+                let key = key?;
+                decrypt_with(&key, &params.check, &[]).map_err(|_| anyhow::anyhow!("Wrong passphrase"))?;
+                if key_path.exists() {
+                    // Left behind by a `set-passphrase` that stopped after committing.
+                    warn!("Removing stale key file {}", key_path.display());
+                    fs::remove_file(&key_path)?;
+                }
+                key
+            }
+            None if key_path.exists() => {
+                Self::recover_pending_key(&conn, &base_dir)?;
+                read_key_file(&key_path)?
+            }
+            None => {
+                let mut k = [0u8; 32];
+                thread_rng().fill_bytes(&mut k);
+                fs::write(&key_path, k)?;
+                k
             }
-            let mut arr = [0u8; 32];
-            arr.copy_from_slice(&k);
-            k.zeroize();
-            arr
-        } else {
-            let mut k = [0u8; 32];
-            thread_rng().fill_bytes(&mut k);
-            fs::write(&key_path, &k)?;
-            k
         };
 
-        let db_path = base_dir.join("koral.db");
+        let mut keys = HashMap::from([(0, root)]);
+        let mut stmt = conn.prepare("SELECT version, wrapped FROM master_keys")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (version, wrapped) = row?;
+            let mut plain = decrypt_with(&root, &wrapped, &[])
+                .with_context(|| format!("Failed to unwrap master key version {}", version))?;
+            let key = <[u8; 32]>::try_from(plain.as_slice()).context("Invalid master key length");
+            plain.zeroize();
+            keys.insert(version, key?);
+        }
+        let current_key = keys.keys().copied().max().unwrap_or(0);
+
         let storage = Self {
             base_dir,
             db_path,
-            key,
+            keys,
+            current_key,
         };
-
-        storage.setup_db()?;
+        storage.upgrade_settings()?;
         Ok(storage)
     }
 
-    fn setup_db(&self) -> Result<()> {
+    /// Finishes a `rotate-key` that stopped between committing and renaming
+    /// the new key file into place, or discards the file of one that stopped
+    /// before committing. Once committed, every master key is wrapped by the
+    /// new root key.
+    fn recover_pending_key(conn: &Connection, base_dir: &Path) -> Result<()> {
+        let pending = base_dir.join(PENDING_KEY_FILE);
+        if !pending.exists() {
+            return Ok(());
+        }
+        let mut stmt = conn.prepare("SELECT wrapped FROM master_keys")?;
+        let wrapped: Vec<String> = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        let committed = match read_key_file(&pending) {
+            Ok(mut key) => {
+                let unwraps = !wrapped.is_empty() && wrapped.iter().all(|w| decrypt_with(&key, w, &[]).is_ok());
+                key.zeroize();
+                unwraps
+            }
+            // Cut short while being written, so before the commit.
+            Err(_) => false,
+        };
+        if committed {
+            warn!("Completing an interrupted key rotation");
+            fs::rename(&pending, base_dir.join(".key"))?;
+        } else {
+            warn!("Discarding the key file of an interrupted key rotation");
+            fs::remove_file(&pending)?;
+        }
+        Ok(())
+    }
+
+    fn kdf_params(conn: &Connection) -> Result<Option<KdfParams>> {
+        let value: Option<String> = conn
+            .query_row("SELECT value FROM settings WHERE key = ?1", [KDF_SETTING], |row| row.get(0))
+            .optional()?;
+        value.map(|v| serde_json::from_str(&v).context("Invalid master key parameters")).transpose()
+    }
+
+    /// Whether the root key is derived from a passphrase rather than read
+    /// from the `.key` file.
+    pub fn is_passphrase_protected(&self) -> Result<bool> {
         let conn = Connection::open(&self.db_path)?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS settings (
-                id INTEGER PRIMARY KEY,
-                key TEXT UNIQUE,
-                value TEXT,
-                is_encrypted INTEGER DEFAULT 0
-            )",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY,
-                telegram_id INTEGER UNIQUE,
-                is_admin INTEGER DEFAULT 0
-            )",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS history (
-                id INTEGER PRIMARY KEY,
-                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
-                event TEXT
-            )",
-            [],
+        Ok(Self::kdf_params(&conn)?.is_some())
+    }
+
+    /// Marks the database as in use by a running daemon. The lock lasts until
+    /// the returned guard is dropped, or the process exits. Fails if another
+    /// daemon holds it.
+    pub fn lock_daemon(&self) -> Result<DaemonLock> {
+        try_lock_daemon(&self.base_dir, || format!("Another koralreef daemon is already using {}", self.base_dir.display()))
+    }
+
+    /// Key changes cannot be picked up by a running daemon: it loaded its
+    /// keys at start and, with a passphrase, cannot derive a new root key.
+    /// The returned lock keeps a daemon from starting until they are done.
+    fn ensure_no_daemon(&self) -> Result<DaemonLock> {
+        try_lock_daemon(&self.base_dir, || "The koralreef daemon is running; stop it before changing keys".to_string())
+    }
+
+    /// Version of the key new ciphertexts are written with.
+    pub fn key_version(&self) -> u32 {
+        self.current_key
+    }
+
+    /// Switches to (or changes) a passphrase-derived root key. The master keys
+    /// and any settings still encrypted under the old root key are
+    /// re-encrypted in the same transaction that stores the new salt, then the
+    /// `.key` file is deleted. Refused while a daemon is running.
+    pub fn set_passphrase(&mut self, passphrase: &str) -> Result<()> {
+        let _lock = self.ensure_no_daemon()?;
+        let mut params = KdfParams::generate();
+        let root = params.derive_key(passphrase)?;
+        params.check = encrypt_with(&root, KEY_CHECK, &[])?;
+
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        for (version, key) in self.keys.iter().filter(|(v, _)| **v != 0) {
+            tx.execute("UPDATE master_keys SET wrapped = ?1 WHERE version = ?2", (encrypt_with(&root, key, &[])?, version))?;
+        }
+        for (name, value) in Self::encrypted_settings(&tx)? {
+            if Envelope::parse(&value)?.key_version != 0 {
+                continue;
+            }
+            let mut plain = self.decrypt(&name, &value)?;
+            let sealed = seal(0, &root, &name, &plain);
+            plain.zeroize();
+            tx.execute("UPDATE settings SET value = ?1 WHERE key = ?2", (sealed?, name))?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value, is_encrypted) VALUES (?1, ?2, 0)",
+            (KDF_SETTING, serde_json::to_string(&params)?),
         )?;
+        tx.commit()?;
+        self.keys.insert(0, root);
+
+        let key_path = self.base_dir.join(".key");
+        if key_path.exists() {
+            fs::remove_file(&key_path)?;
+        }
         Ok(())
     }
 
-    pub fn encrypt(&self, data: &[u8]) -> Result<String> {
-        let cipher = Aes256Gcm::new(&self.key.into());
-        let mut nonce_bytes = [0u8; 12];
-        thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-        
-        let ciphertext = cipher.encrypt(nonce, data)
-            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
-        
-        let mut combined = nonce_bytes.to_vec();
-        combined.extend(ciphertext);
-        Ok(base64::Engine::encode(&base64::prelude::BASE64_STANDARD, combined))
+    /// Replaces both the root key and the master key, and re-encrypts every
+    /// encrypted setting under the new master key, all in one transaction.
+    /// The new root key is a fresh `.key` file or, for a passphrase-protected
+    /// database, derived from `passphrase` under a new salt. Superseded
+    /// master keys are deleted, so the old root key decrypts nothing. Returns
+    /// the new version and the number of settings rewritten. Refused while a
+    /// daemon is running.
+    pub fn rotate_key(&mut self, passphrase: Option<&str>) -> Result<(u32, usize)> {
+        let _lock = self.ensure_no_daemon()?;
+        let mut conn = Connection::open(&self.db_path)?;
+        let pending = self.base_dir.join(PENDING_KEY_FILE);
+        let (params, mut root) = match Self::kdf_params(&conn)? {
+            Some(old) => {
+                let passphrase = passphrase.context("The database is passphrase-protected; its passphrase is needed to rotate the key")?;
+                let mut current = old.derive_key(passphrase)?;
+                let matches = current == self.keys[&0];
+                current.zeroize();
+                if !matches {
+                    bail!("Wrong passphrase");
+                }
+                let params = KdfParams::generate();
+                let root = params.derive_key(passphrase)?;
+                (Some(params), root)
+            }
+            None => {
+                let mut root = [0u8; 32];
+                thread_rng().fill_bytes(&mut root);
+                // Written before the commit so the new root is never lost; an
+                // open after a crash finishes or discards it.
+                write_key_file(&pending, &root)?;
+                (None, root)
+            }
+        };
+        let mut key = [0u8; 32];
+        thread_rng().fill_bytes(&mut key);
+
+        let tx = conn.transaction()?;
+        let version: u32 = tx.query_row("SELECT COALESCE(MAX(version), 0) + 1 FROM master_keys", [], |row| row.get(0))?;
+        tx.execute(
+            "INSERT INTO master_keys (version, wrapped) VALUES (?1, ?2)",
+            (version, encrypt_with(&root, &key, &[])?),
+        )?;
+        let rows = Self::encrypted_settings(&tx)?;
+        for (name, value) in &rows {
+            let mut plain = self.decrypt(name, value)?;
+            let sealed = seal(version, &key, name, &plain);
+            plain.zeroize();
+            tx.execute("UPDATE settings SET value = ?1 WHERE key = ?2", (sealed?, name))?;
+        }
+        tx.execute("DELETE FROM master_keys WHERE version != ?1", [version])?;
+        if let Some(mut params) = params {
+            params.check = encrypt_with(&root, KEY_CHECK, &[])?;
+            tx.execute("UPDATE settings SET value = ?1 WHERE key = ?2", (serde_json::to_string(&params)?, KDF_SETTING))?;
+        }
+        tx.commit()?;
+        if pending.exists() {
+            fs::rename(&pending, self.base_dir.join(".key"))?;
+        }
+
+        for old in self.keys.values_mut() {
+            old.zeroize();
+        }
+        self.keys = HashMap::from([(0, root), (version, key)]);
+        self.current_key = version;
+        root.zeroize();
+        key.zeroize();
+        Ok((version, rows.len()))
+    }
+
+    fn encrypted_settings(tx: &rusqlite::Transaction) -> Result<Vec<(String, String)>> {
+        let mut stmt = tx.prepare("SELECT key, value FROM settings WHERE is_encrypted = 1")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn schema_version(&self) -> Result<u32> {
+        let conn = Connection::open(&self.db_path)?;
+        migrations::schema_version(&conn)
+    }
+
+    /// Encrypts `data` as the value of setting `name` under the current key.
+    /// The ciphertext is bound to `name`, so it cannot be moved to another row.
+    pub fn encrypt(&self, name: &str, data: &[u8]) -> Result<String> {
+        seal(self.current_key, &self.keys[&self.current_key], name, data)
     }
 
-    pub fn decrypt(&self, encoded: &str) -> Result<Vec<u8>> {
-        let combined = base64::Engine::decode(&base64::prelude::BASE64_STANDARD, encoded)?;
-        if combined.len() < 12 {
-            anyhow::bail!("Invalid encrypted data");
+    /// Decrypts the value of setting `name`. Fails with a tamper error when
+    /// the value was not sealed for `name`.
+    pub fn decrypt(&self, name: &str, encoded: &str) -> Result<Vec<u8>> {
+        let envelope = Envelope::parse(encoded)?;
+        if envelope.format != SETTING_FORMAT {
+            bail!("Setting '{}' is not bound to its name; the database may have been tampered with", name);
         }
+        let key = self.key(envelope.key_version)?;
+        decrypt_with(key, envelope.body, &setting_aad(name))
+            .map_err(|_| anyhow::anyhow!("Setting '{}' failed its integrity check; the database may have been tampered with", name))
+    }
 
-        let (nonce_bytes, ciphertext) = combined.split_at(12);
-        let cipher = Aes256Gcm::new(&self.key.into());
-        let nonce = Nonce::from_slice(nonce_bytes);
-        
-        cipher.decrypt(nonce, ciphertext)
-            .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+    fn key(&self, version: u32) -> Result<&[u8; 32]> {
+        self.keys.get(&version).ok_or_else(|| anyhow::anyhow!("Unknown key version {}", version))
+    }
+
+    /// Re-seals settings written before values were bound to their names.
+    /// Runs once per database, at open; afterwards an unbound value is only
+    /// ever reported as tampering.
+    fn upgrade_settings(&self) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        let format: Option<String> = tx
+            .query_row("SELECT value FROM settings WHERE key = ?1", [FORMAT_SETTING], |row| row.get(0))
+            .optional()?;
+        if format.is_some() {
+            return Ok(());
+        }
+        let mut upgraded = 0;
+        for (name, value) in Self::encrypted_settings(&tx)? {
+            let envelope = Envelope::parse(&value)?;
+            if envelope.format == SETTING_FORMAT {
+                continue;
+            }
+            let mut plain = decrypt_with(self.key(envelope.key_version)?, envelope.body, &[])
+                .with_context(|| format!("Failed to decrypt setting '{}'", name))?;
+            let sealed = self.encrypt(&name, &plain);
+            plain.zeroize();
+            tx.execute("UPDATE settings SET value = ?1 WHERE key = ?2", (sealed?, name))?;
+            upgraded += 1;
+        }
+        tx.execute(
+            "INSERT INTO settings (key, value, is_encrypted) VALUES (?1, ?2, 0)",
+            (FORMAT_SETTING, SETTING_FORMAT.to_string()),
+        )?;
+        tx.commit()?;
+        if upgraded > 0 {
+            log::info!("Bound {} encrypted settings to their names", upgraded);
+        }
+        Ok(())
     }
 
     pub fn set_setting(&self, key: &str, value: &str, encrypt: bool) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
         let final_value = if encrypt {
-            self.encrypt(value.as_bytes())?
+            self.encrypt(key, value.as_bytes())?
         } else {
             value.to_string()
         };
@@ -134,7 +644,7 @@ impl Storage {
             let value: String = row.get(0)?;
             let is_encrypted: i32 = row.get(1)?;
             if is_encrypted == 1 {
-                let decrypted = self.decrypt(&value)?;
+                let decrypted = self.decrypt(key, &value)?;
                 Ok(Some(String::from_utf8(decrypted)?))
             } else {
                 Ok(Some(value))
@@ -144,27 +654,93 @@ impl Storage {
         }
     }
 
-    pub fn get_admin(&self) -> Result<Option<u64>> {
+    /// The role of `telegram_id`, or `None` if they are not a user.
+    pub fn user_role(&self, telegram_id: u64) -> Result<Option<Role>> {
         let conn = Connection::open(&self.db_path)?;
-        let mut stmt = conn.prepare("SELECT telegram_id FROM users WHERE is_admin = 1 LIMIT 1")?;
-        let mut rows = stmt.query([])?;
-        if let Some(row) = rows.next()? {
-            let id: i64 = row.get(0)?;
-            Ok(Some(id as u64))
-        } else {
-            Ok(None)
+        let role: Option<String> = conn
+            .query_row("SELECT role FROM users WHERE telegram_id = ?1", [telegram_id as i64], |row| row.get(0))
+            .optional()?;
+        role.map(|role| Role::parse(&role)).transpose()
+    }
+
+    /// All users, owners first.
+    pub fn users(&self) -> Result<Vec<User>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT telegram_id, role FROM users
+             ORDER BY CASE role WHEN 'owner' THEN 0 WHEN 'operator' THEN 1 ELSE 2 END, telegram_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        let mut users = Vec::new();
+        for row in rows {
+            let (telegram_id, role) = row?;
+            users.push(User { telegram_id: telegram_id as u64, role: Role::parse(&role)? });
         }
+        Ok(users)
+    }
+
+    /// Telegram ids that receive sweep notifications.
+    pub fn owners(&self) -> Result<Vec<u64>> {
+        Ok(self.users()?
+            .into_iter()
+            .filter(|user| user.role == Role::Owner)
+            .map(|user| user.telegram_id)
+            .collect())
     }
 
-    pub fn set_admin(&self, telegram_id: u64) -> Result<()> {
+    /// Adds the configured `authorized_user_ids` as owners. Users already in
+    /// the table keep the role they were given, so this is safe to run at
+    /// every start. Returns how many were added.
+    pub fn seed_users(&self, owners: &[u64]) -> Result<usize> {
         let conn = Connection::open(&self.db_path)?;
-        conn.execute(
-            "INSERT OR IGNORE INTO users (telegram_id, is_admin) VALUES (?1, 1)",
+        let mut added = 0;
+        for id in owners {
+            added += conn.execute(
+                "INSERT OR IGNORE INTO users (telegram_id, role) VALUES (?1, 'owner')",
+                [*id as i64],
+            )?;
+        }
+        Ok(added)
+    }
+
+    /// Makes `telegram_id` the owner if there are no users at all, so an
+    /// install without `authorized_user_ids` can still be claimed. Returns
+    /// false once anyone is registered.
+    pub fn claim_first_owner(&self, telegram_id: u64) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let inserted = conn.execute(
+            "INSERT INTO users (telegram_id, role) SELECT ?1, 'owner' WHERE NOT EXISTS (SELECT 1 FROM users)",
             [telegram_id as i64],
         )?;
+        Ok(inserted > 0)
+    }
+
+    /// Adds `telegram_id` with `role`, or changes their role. Refuses to
+    /// demote the last owner.
+    pub fn set_user_role(&self, telegram_id: u64, role: Role) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        if role != Role::Owner {
+            ensure_other_owner(&tx, telegram_id)?;
+        }
+        tx.execute(
+            "INSERT INTO users (telegram_id, role) VALUES (?1, ?2)
+             ON CONFLICT(telegram_id) DO UPDATE SET role = excluded.role",
+            (telegram_id as i64, role.as_str()),
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
+    /// Removes `telegram_id`, returning false if they were not a user.
+    /// Refuses to remove the last owner.
+    pub fn revoke_user(&self, telegram_id: u64) -> Result<bool> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        ensure_other_owner(&tx, telegram_id)?;
+        let removed = tx.execute("DELETE FROM users WHERE telegram_id = ?1", [telegram_id as i64])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
     pub fn log_event(&self, event: &str) -> Result<()> {
         let conn = Connection::open(&self.db_path)?;
         conn.execute(
@@ -190,6 +766,382 @@ impl Storage {
         Ok(results)
     }
 
+    pub fn record_simulation(&self, sim: &Simulation) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let accounts: Vec<String> = sim.accounts.iter().map(|p| p.to_string()).collect();
+        conn.execute(
+            "INSERT INTO simulations (accounts, units_consumed, error, logs) VALUES (?1, ?2, ?3, ?4)",
+            (
+                accounts.join(","),
+                sim.units_consumed.map(|u| u as i64),
+                &sim.error,
+                sim.logs.join("\n"),
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Persists a scan report and remembers when each account was first seen,
+    /// which is what report ages are measured from. Also tracks since when
+    /// each account has been continuously empty: a non-zero balance or a
+    /// closed account clears its entry. Only the latest [`SCAN_REPORTS_KEPT`]
+    /// reports are kept.
+    pub fn save_scan_report(&self, report: &ScanReport) -> Result<i64> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        let reclaimable = report.entries.iter().filter(|e| e.is_reclaimable()).count();
+        tx.execute(
+            "INSERT INTO scan_reports (scanned_at, total, reclaimable) VALUES (?1, ?2, ?3)",
+            (report.scanned_at, report.entries.len() as i64, reclaimable as i64),
+        )?;
+        let report_id = tx.last_insert_rowid();
+        for entry in &report.entries {
+            let status = match &entry.verdict {
+                Ok(()) => "reclaimable".to_string(),
+                Err(reason) => reason.to_string(),
+            };
+            tx.execute(
+                "INSERT INTO scan_entries (report_id, pubkey, mint, lamports, status) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (
+                    report_id,
+                    entry.pubkey.to_string(),
+                    entry.mint.map(|m| m.to_string()),
+                    entry.account.lamports as i64,
+                    status,
+                ),
+            )?;
+            tx.execute(
+                "INSERT OR IGNORE INTO account_sightings (pubkey, first_seen) VALUES (?1, ?2)",
+                (entry.pubkey.to_string(), report.scanned_at),
+            )?;
+            if entry.amount == Some(0) {
+                tx.execute(
+                    "INSERT OR IGNORE INTO empty_accounts (pubkey, empty_since) VALUES (?1, ?2)",
+                    (entry.pubkey.to_string(), report.scanned_at),
+                )?;
+            } else {
+                tx.execute("DELETE FROM empty_accounts WHERE pubkey = ?1", [entry.pubkey.to_string()])?;
+            }
+        }
+        tx.execute(
+            "DELETE FROM empty_accounts WHERE pubkey NOT IN (SELECT pubkey FROM scan_entries WHERE report_id = ?1)",
+            [report_id],
+        )?;
+        tx.execute(
+            "DELETE FROM scan_entries WHERE report_id NOT IN (SELECT id FROM scan_reports ORDER BY id DESC LIMIT ?1)",
+            [SCAN_REPORTS_KEPT],
+        )?;
+        tx.execute(
+            "DELETE FROM scan_reports WHERE id NOT IN (SELECT id FROM scan_reports ORDER BY id DESC LIMIT ?1)",
+            [SCAN_REPORTS_KEPT],
+        )?;
+        tx.commit()?;
+        Ok(report_id)
+    }
+
+    pub fn latest_scan_report(&self) -> Result<Option<StoredScanReport>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT id, datetime(scanned_at, 'unixepoch'), total, reclaimable FROM scan_reports ORDER BY id DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query([])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let report_id: i64 = row.get(0)?;
+        let mut report = StoredScanReport {
+            timestamp: row.get(1)?,
+            total: row.get::<_, i64>(2)? as u64,
+            reclaimable: row.get::<_, i64>(3)? as u64,
+            entries: Vec::new(),
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT e.pubkey, e.mint, e.lamports, r.scanned_at - COALESCE(s.first_seen, r.scanned_at), e.status
+             FROM scan_entries e
+             JOIN scan_reports r ON r.id = e.report_id
+             LEFT JOIN account_sightings s ON s.pubkey = e.pubkey
+             WHERE e.report_id = ?1
+             ORDER BY e.status != 'reclaimable', e.lamports DESC",
+        )?;
+        let rows = stmt.query_map([report_id], |row| {
+            Ok(ScanReportRow {
+                pubkey: row.get(0)?,
+                mint: row.get(1)?,
+                lamports: row.get::<_, i64>(2)? as u64,
+                age_secs: row.get::<_, i64>(3)?.max(0) as u64,
+                status: row.get(4)?,
+            })
+        })?;
+        for row in rows {
+            report.entries.push(row?);
+        }
+        Ok(Some(report))
+    }
+
+    pub fn first_seen(&self) -> Result<HashMap<Pubkey, i64>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT pubkey, first_seen FROM account_sightings")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+        let mut seen = HashMap::new();
+        for row in rows {
+            let (pubkey, first_seen) = row?;
+            if let Ok(pubkey) = Pubkey::from_str(&pubkey) {
+                seen.insert(pubkey, first_seen);
+            }
+        }
+        Ok(seen)
+    }
+
+    pub fn empty_since(&self) -> Result<HashMap<Pubkey, i64>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT pubkey, empty_since FROM empty_accounts")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+        let mut since = HashMap::new();
+        for row in rows {
+            let (pubkey, empty_since) = row?;
+            if let Ok(pubkey) = Pubkey::from_str(&pubkey) {
+                since.insert(pubkey, empty_since);
+            }
+        }
+        Ok(since)
+    }
+
+    /// Adds a rule, returning false if an identical rule already exists.
+    pub fn add_rule(&self, rule: &Rule) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO whitelist_rules (kind, value) VALUES (?1, ?2)",
+            (rule.kind(), rule.value()),
+        )?;
+        Ok(inserted > 0)
+    }
+
+    /// Removes a rule, returning false if it did not exist.
+    pub fn remove_rule(&self, rule: &Rule) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        let removed = conn.execute(
+            "DELETE FROM whitelist_rules WHERE kind = ?1 AND value = ?2",
+            (rule.kind(), rule.value()),
+        )?;
+        Ok(removed > 0)
+    }
+
+    pub fn list_rules(&self) -> Result<Vec<Rule>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT kind, value FROM whitelist_rules ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        let mut rules = Vec::new();
+        for row in rows {
+            let (kind, value) = row?;
+            rules.push(Rule::parse(&kind, &value)?);
+        }
+        Ok(rules)
+    }
+
+    /// Records a signed batch as planned. Must succeed before the batch is
+    /// broadcast.
+    pub fn journal_batch(&self, signature: &Signature, blockhash: &Hash, accounts: &[Pubkey], lamports: u64, fee_lamports: u64) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        let accounts: Vec<String> = accounts.iter().map(|p| p.to_string()).collect();
+        conn.execute(
+            "INSERT INTO reclaim_journal (signature, blockhash, accounts, lamports, fee_lamports, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                signature.to_string(),
+                blockhash.to_string(),
+                accounts.join(","),
+                lamports as i64,
+                fee_lamports as i64,
+                JournalStatus::Planned.as_str(),
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn set_journal_status(&self, signature: &Signature, status: JournalStatus, slot: Option<u64>) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "UPDATE reclaim_journal SET status = ?1, slot = COALESCE(?2, slot), updated_at = CURRENT_TIMESTAMP
+             WHERE signature = ?3",
+            (status.as_str(), slot.map(|s| s as i64), signature.to_string()),
+        )?;
+        Ok(())
+    }
+
+    /// Batches that were planned or sent but never resolved, oldest first.
+    pub fn in_flight_batches(&self) -> Result<Vec<JournalEntry>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT signature, blockhash, accounts, lamports, fee_lamports, status, slot
+             FROM reclaim_journal WHERE status IN ('planned', 'sent') ORDER BY created_at, rowid",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+            ))
+        })?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let (signature, blockhash, accounts, lamports, fee_lamports, status, slot) = row?;
+            entries.push(JournalEntry {
+                signature: Signature::from_str(&signature)?,
+                blockhash: Hash::from_str(&blockhash)?,
+                accounts: accounts
+                    .split(',')
+                    .filter(|p| !p.is_empty())
+                    .map(Pubkey::from_str)
+                    .collect::<Result<_, _>>()?,
+                lamports: lamports as u64,
+                fee_lamports: fee_lamports as u64,
+                status: JournalStatus::parse(&status)?,
+                slot: slot.map(|s| s as u64),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Adds closed accounts to the reclamations ledger. Recording the same
+    /// account and signature twice is a no-op.
+    pub fn record_reclamations(&self, reclamations: &[Reclamation]) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        for r in reclamations {
+            tx.execute(
+                "INSERT OR IGNORE INTO reclamations
+                 (pubkey, mint, lamports, signature, slot, fee_lamports, treasury, reclaimed_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                (
+                    r.pubkey.to_string(),
+                    r.mint.map(|m| m.to_string()),
+                    r.lamports as i64,
+                    r.signature.to_string(),
+                    r.slot.map(|s| s as i64),
+                    r.fee_lamports as i64,
+                    r.treasury.to_string(),
+                    r.reclaimed_at,
+                ),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Totals per UTC day, most recent first.
+    pub fn reclamations_by_day(&self) -> Result<Vec<ReclamationTotals>> {
+        self.reclamation_totals("date(reclaimed_at, 'unixepoch')", "key DESC")
+    }
+
+    /// Totals per mint, largest first. Accounts with an unknown mint are
+    /// grouped under "unknown".
+    pub fn reclamations_by_mint(&self) -> Result<Vec<ReclamationTotals>> {
+        self.reclamation_totals("COALESCE(mint, 'unknown')", "SUM(lamports) DESC")
+    }
+
+    /// Totals per treasury, largest first.
+    pub fn reclamations_by_treasury(&self) -> Result<Vec<ReclamationTotals>> {
+        self.reclamation_totals("treasury", "SUM(lamports) DESC")
+    }
+
+    fn reclamation_totals(&self, group: &str, order: &str) -> Result<Vec<ReclamationTotals>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} AS key, COUNT(*), SUM(lamports), SUM(fee_lamports)
+             FROM reclamations GROUP BY key ORDER BY {}",
+            group, order
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ReclamationTotals {
+                key: row.get(0)?,
+                accounts: row.get::<_, i64>(1)? as u64,
+                lamports: row.get::<_, i64>(2)? as u64,
+                fee_lamports: row.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        let mut totals = Vec::new();
+        for row in rows {
+            totals.push(row?);
+        }
+        Ok(totals)
+    }
+
+    /// Mint and lamports of `pubkey` as last recorded by a scan.
+    pub fn last_scanned(&self, pubkey: &Pubkey) -> Result<Option<(Option<Pubkey>, u64)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT mint, lamports FROM scan_entries WHERE pubkey = ?1 ORDER BY id DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query([pubkey.to_string()])?;
+        let Some(row) = rows.next()? else {
+            return Ok(None);
+        };
+        let mint: Option<String> = row.get(0)?;
+        let lamports: i64 = row.get(1)?;
+        Ok(Some((mint.and_then(|m| Pubkey::from_str(&m).ok()), lamports as u64)))
+    }
+
+    pub fn lifetime_stats(&self) -> Result<LifetimeStats> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare("SELECT reclaimed_lamports, accounts_closed, last_scan_at FROM app_stats WHERE id = 1")?;
+        let mut rows = stmt.query([])?;
+        let Some(row) = rows.next()? else {
+            return Ok(LifetimeStats::default());
+        };
+        Ok(LifetimeStats {
+            reclaimed_lamports: row.get::<_, i64>(0)? as u64,
+            accounts_closed: row.get::<_, i64>(1)? as u64,
+            last_scan_at: row.get(2)?,
+        })
+    }
+
+    /// Adds a completed scan's closes to the lifetime totals and returns the
+    /// updated stats.
+    pub fn record_scan(&self, scanned_at: i64, reclaimed_lamports: u64, accounts_closed: u64) -> Result<LifetimeStats> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO app_stats (id, reclaimed_lamports, accounts_closed, last_scan_at) VALUES (1, ?1, ?2, ?3)
+             ON CONFLICT(id) DO UPDATE SET
+                reclaimed_lamports = reclaimed_lamports + excluded.reclaimed_lamports,
+                accounts_closed = accounts_closed + excluded.accounts_closed,
+                last_scan_at = excluded.last_scan_at",
+            (reclaimed_lamports as i64, accounts_closed as i64, scanned_at),
+        )?;
+        self.lifetime_stats()
+    }
+
+    /// The stored pause, including one whose time has run out; see
+    /// [`Pause::has_ended`].
+    pub fn pause(&self) -> Result<Option<Pause>> {
+        let conn = Connection::open(&self.db_path)?;
+        Ok(conn
+            .query_row("SELECT paused_at, paused_until, paused_by FROM sentinel_pause WHERE id = 1", [], |row| {
+                Ok(Pause { paused_at: row.get(0)?, until: row.get(1)?, by: row.get(2)? })
+            })
+            .optional()?)
+    }
+
+    /// Stops scheduled sweeps, replacing any earlier pause.
+    pub fn set_pause(&self, pause: &Pause) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO sentinel_pause (id, paused_at, paused_until, paused_by) VALUES (1, ?1, ?2, ?3)",
+            (pause.paused_at, pause.until, &pause.by),
+        )?;
+        Ok(())
+    }
+
+    /// Clears the pause, returning false if sweeps were not paused.
+    pub fn resume(&self) -> Result<bool> {
+        let conn = Connection::open(&self.db_path)?;
+        Ok(conn.execute("DELETE FROM sentinel_pause WHERE id = 1", [])? > 0)
+    }
+
     pub fn save_keypair(&self, keypair_json: &str) -> Result<()> {
         self.set_setting("solana_keypair", keypair_json, true)
     }
@@ -197,4 +1149,123 @@ impl Storage {
     pub fn get_keypair(&self) -> Result<Option<String>> {
         self.get_setting("solana_keypair")
     }
+
+    /// The operator keypair: the one imported with `--import-key`, or else
+    /// the JSON file at `keypair_path`.
+    pub fn operator_keypair(&self, keypair_path: &str) -> Result<Keypair> {
+        let key_json = if let Some(key_json) = self.get_keypair()? {
+            key_json
+        } else if !keypair_path.is_empty() {
+            fs::read_to_string(keypair_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read keypair file at {}: {}", keypair_path, e))?
+        } else {
+            bail!("No keypair found in database or config file");
+        };
+        let keypair_vec: Vec<u8> = serde_json::from_str(&key_json)?;
+        Ok(Keypair::from_bytes(&keypair_vec)?)
+    }
+}
+
+/// Format of setting ciphertexts that carry their setting name as associated
+/// data. Format 1 values (untagged, or tagged only with a key version) predate
+/// it and are upgraded at open.
+const SETTING_FORMAT: u8 = 2;
+
+fn setting_aad(name: &str) -> Vec<u8> {
+    format!("koralreef/setting/v{}/{}", SETTING_FORMAT, name).into_bytes()
+}
+
+/// A stored ciphertext: `v2.k<version>:<base64>`, or for format 1
+/// `k<version>:<base64>` or bare base64 (key version 0). Base64 never
+/// contains ':', so the forms are unambiguous.
+struct Envelope<'a> {
+    format: u8,
+    key_version: u32,
+    body: &'a str,
+}
+
+impl<'a> Envelope<'a> {
+    fn parse(encoded: &'a str) -> Result<Self> {
+        let Some((tag, body)) = encoded.split_once(':') else {
+            return Ok(Envelope { format: 1, key_version: 0, body: encoded });
+        };
+        let (format, key) = match tag.split_once('.') {
+            Some((format, key)) => (format.strip_prefix('v').and_then(|f| f.parse().ok()), key),
+            None => (Some(1), tag),
+        };
+        match (format, key.strip_prefix('k').and_then(|v| v.parse().ok())) {
+            (Some(format), Some(key_version)) => Ok(Envelope { format, key_version, body }),
+            _ => bail!("Invalid ciphertext tag '{}'", tag),
+        }
+    }
+}
+
+fn seal(version: u32, key: &[u8; 32], name: &str, data: &[u8]) -> Result<String> {
+    Ok(format!("v{}.k{}:{}", SETTING_FORMAT, version, encrypt_with(key, data, &setting_aad(name))?))
+}
+
+/// Takes the daemon lock of `base_dir`, failing with `busy` when another
+/// process holds it.
+pub(crate) fn try_lock_daemon(base_dir: &Path, busy: impl FnOnce() -> String) -> Result<DaemonLock> {
+    let path = base_dir.join(DAEMON_LOCK_FILE);
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => Ok(DaemonLock { _file: file }),
+        Err(fs::TryLockError::WouldBlock) => bail!("{}", busy()),
+        Err(fs::TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+fn read_key_file(path: &Path) -> Result<[u8; 32]> {
+    let mut k = fs::read(path)?;
+    if k.len() != 32 {
+        k.zeroize();
+        anyhow::bail!("Invalid key length");
+    }
+    let mut arr = [0u8; 32];
+    arr.copy_from_slice(&k);
+    k.zeroize();
+    Ok(arr)
+}
+
+/// Writes and syncs a key file, so it is on disk before anything depends on it.
+fn write_key_file(path: &Path, key: &[u8; 32]) -> Result<()> {
+    use std::io::Write;
+    let mut file = fs::File::create(path).with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(key)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn encrypt_with(key: &[u8; 32], data: &[u8], aad: &[u8]) -> Result<String> {
+    let cipher = Aes256Gcm::new(key.into());
+    let mut nonce_bytes = [0u8; 12];
+    thread_rng().fill_bytes(&mut nonce_bytes);
+    let nonce = Nonce::from(nonce_bytes);
+
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: data, aad })
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+
+    let mut combined = nonce_bytes.to_vec();
+    combined.extend(ciphertext);
+    Ok(base64::Engine::encode(&base64::prelude::BASE64_STANDARD, combined))
+}
+
+fn decrypt_with(key: &[u8; 32], encoded: &str, aad: &[u8]) -> Result<Vec<u8>> {
+    let combined = base64::Engine::decode(&base64::prelude::BASE64_STANDARD, encoded)?;
+    if combined.len() < 12 {
+        anyhow::bail!("Invalid encrypted data");
+    }
+
+    let (nonce_bytes, ciphertext) = combined.split_at(12);
+    let cipher = Aes256Gcm::new(key.into());
+    let nonce = Nonce::from(<[u8; 12]>::try_from(nonce_bytes)?);
+
+    cipher.decrypt(&nonce, Payload { msg: ciphertext, aad })
+        .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
 }
@@ -0,0 +1,202 @@
+//! Passphrase-encrypted archives of the data directory.
+//!
+//! Layout: `KORALBAK`, a format version byte, a little-endian `u32` length and
+//! that many bytes of JSON [`KdfParams`], a 12-byte nonce, then the AES-GCM
+//! ciphertext of the payload. Everything before the nonce is authenticated as
+//! associated data, so any change to the header or payload fails the
+//! integrity check. The payload is a list of files, each a `u16` name length,
+//! the name, a `u64` data length and the data.
+
+use std::fs;
+use std::path::Path;
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{bail, Context, Result};
+use rand::{thread_rng, RngCore};
+use rusqlite::Connection;
+use zeroize::Zeroize;
+use super::keys::KdfParams;
+use super::{try_lock_daemon, Storage};
+
+const MAGIC: &[u8; 8] = b"KORALBAK";
+
+/// Archive format written by this build.
+pub const BACKUP_VERSION: u8 = 1;
+
+const DB_FILE: &str = "koral.db";
+const KEY_FILE: &str = ".key";
+
+/// What a backup contained.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupSummary {
+    pub files: Vec<String>,
+    pub bytes: u64,
+}
+
+impl Storage {
+    /// Writes a consistent snapshot of the database, and the key file when
+    /// the install has one, to `archive` encrypted under `passphrase`.
+    pub fn backup(&self, archive: &Path, passphrase: &str) -> Result<BackupSummary> {
+        let snapshot = self.base_dir.join(".backup-snapshot.db");
+        if snapshot.exists() {
+            fs::remove_file(&snapshot)?;
+        }
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("VACUUM INTO ?1", [snapshot.to_string_lossy()])
+            .context("Failed to snapshot the database")?;
+        let db = fs::read(&snapshot);
+        fs::remove_file(&snapshot)?;
+
+        let mut files = vec![(DB_FILE.to_string(), db?)];
+        let key_path = self.base_dir.join(KEY_FILE);
+        if key_path.exists() {
+            files.push((KEY_FILE.to_string(), fs::read(&key_path)?));
+        }
+        let summary = BackupSummary {
+            files: files.iter().map(|(name, _)| name.clone()).collect(),
+            bytes: files.iter().map(|(_, data)| data.len() as u64).sum(),
+        };
+
+        let mut payload = encode_files(&files);
+        for (_, data) in &mut files {
+            data.zeroize();
+        }
+        let sealed = seal(&payload, passphrase);
+        payload.zeroize();
+        fs::write(archive, sealed?).with_context(|| format!("Failed to write {}", archive.display()))?;
+        Ok(summary)
+    }
+}
+
+/// Restores an archive written by [`Storage::backup`] into `base_dir`.
+/// Refuses to replace an existing database unless `force` is set, and to run
+/// while a daemon is using `base_dir`. Nothing is written unless the archive
+/// decrypts and passes its integrity check.
+pub fn restore(archive: &Path, passphrase: &str, base_dir: &Path, force: bool) -> Result<BackupSummary> {
+    let db_path = base_dir.join(DB_FILE);
+    if db_path.exists() && !force {
+        bail!("{} already exists; pass --force to overwrite it", db_path.display());
+    }
+
+    let sealed = fs::read(archive).with_context(|| format!("Failed to read {}", archive.display()))?;
+    let mut payload = open(&sealed, passphrase)?;
+    let files = decode_files(&payload);
+    payload.zeroize();
+    let files = files?;
+    if !files.iter().any(|(name, _)| name == DB_FILE) {
+        bail!("Backup does not contain a database");
+    }
+
+    fs::create_dir_all(base_dir)?;
+    // Held until every file is in place, so no daemon starts on a half-restored directory.
+    let _lock = try_lock_daemon(base_dir, || "The koralreef daemon is running; stop it before restoring".to_string())?;
+
+    // Written beside their targets and only renamed once all are written, so
+    // a failure never leaves a half-written file in place.
+    let staged: Vec<_> = files.iter().map(|(name, _)| base_dir.join(format!("{}.restore", name))).collect();
+    let written = files.iter().zip(&staged).try_for_each(|((_, data), tmp)| fs::write(tmp, data));
+    let renamed = written.and_then(|()| {
+        files.iter().zip(&staged).try_for_each(|((name, _), tmp)| fs::rename(tmp, base_dir.join(name)))
+    });
+    if let Err(e) = renamed {
+        for tmp in staged.iter().filter(|tmp| tmp.exists()) {
+            let _ = fs::remove_file(tmp);
+        }
+        return Err(anyhow::Error::new(e).context("Failed to restore the backup"));
+    }
+
+    // Without a key file in the archive the database is passphrase-protected,
+    // and a key file left from the old install would be stale. It is only
+    // removed now, so a failed restore leaves the old install usable.
+    let key_path = base_dir.join(KEY_FILE);
+    if key_path.exists() && !files.iter().any(|(name, _)| name == KEY_FILE) {
+        fs::remove_file(&key_path)?;
+    }
+
+    Ok(BackupSummary {
+        files: files.iter().map(|(name, _)| name.clone()).collect(),
+        bytes: files.iter().map(|(_, data)| data.len() as u64).sum(),
+    })
+}
+
+fn seal(payload: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let params = KdfParams::generate();
+    let mut key = params.derive_key(passphrase)?;
+    let header_json = serde_json::to_vec(&params)?;
+
+    let mut out = MAGIC.to_vec();
+    out.push(BACKUP_VERSION);
+    out.extend((header_json.len() as u32).to_le_bytes());
+    out.extend(&header_json);
+
+    let mut nonce = [0u8; 12];
+    thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = Aes256Gcm::new(&key.into())
+        .encrypt(&Nonce::from(nonce), Payload { msg: payload, aad: &out })
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e));
+    key.zeroize();
+
+    out.extend(nonce);
+    out.extend(ciphertext?);
+    Ok(out)
+}
+
+fn open(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    if sealed.len() < MAGIC.len() + 5 || &sealed[..MAGIC.len()] != MAGIC {
+        bail!("Not a koralreef backup");
+    }
+    let version = sealed[MAGIC.len()];
+    if version != BACKUP_VERSION {
+        bail!("Unsupported backup version {} (this build reads version {})", version, BACKUP_VERSION);
+    }
+    let header_start = MAGIC.len() + 5;
+    let header_len = u32::from_le_bytes(sealed[MAGIC.len() + 1..header_start].try_into()?) as usize;
+    let nonce_start = header_start.checked_add(header_len).filter(|n| n + 12 <= sealed.len())
+        .ok_or_else(|| anyhow::anyhow!("Backup is truncated"))?;
+    let params: KdfParams = serde_json::from_slice(&sealed[header_start..nonce_start])
+        .context("Backup header is corrupt")?;
+
+    let mut key = params.derive_key(passphrase)?;
+    let nonce = <[u8; 12]>::try_from(&sealed[nonce_start..nonce_start + 12])?;
+    let payload = Aes256Gcm::new(&key.into())
+        .decrypt(&Nonce::from(nonce), Payload { msg: &sealed[nonce_start + 12..], aad: &sealed[..nonce_start] })
+        .map_err(|_| anyhow::anyhow!("Backup failed its integrity check: wrong passphrase or corrupted archive"));
+    key.zeroize();
+    payload
+}
+
+fn encode_files(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, data) in files {
+        out.extend((name.len() as u16).to_le_bytes());
+        out.extend(name.as_bytes());
+        out.extend((data.len() as u64).to_le_bytes());
+        out.extend(data);
+    }
+    out
+}
+
+fn decode_files(mut payload: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    fn take<'a>(buf: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+        if buf.len() < n {
+            bail!("Backup payload is truncated");
+        }
+        let (head, tail) = buf.split_at(n);
+        *buf = tail;
+        Ok(head)
+    }
+
+    let mut files = Vec::new();
+    while !payload.is_empty() {
+        let name_len = u16::from_le_bytes(take(&mut payload, 2)?.try_into()?) as usize;
+        let name = String::from_utf8(take(&mut payload, name_len)?.to_vec())?;
+        if name != DB_FILE && name != KEY_FILE {
+            bail!("Unexpected file '{}' in backup", name);
+        }
+        let data_len = u64::from_le_bytes(take(&mut payload, 8)?.try_into()?) as usize;
+        files.push((name, take(&mut payload, data_len)?.to_vec()));
+    }
+    Ok(files)
+}
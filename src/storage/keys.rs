@@ -0,0 +1,174 @@
+//! Master key material: passphrase sources and Argon2id key derivation.
+
+use std::fmt;
+use std::io::Read;
+use anyhow::{bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// Environment variable read for the passphrase when no descriptor is given.
+pub const PASSPHRASE_ENV: &str = "KORALREEF_PASSPHRASE";
+
+/// Environment variable read for the new passphrase by `set-passphrase`.
+pub const NEW_PASSPHRASE_ENV: &str = "KORALREEF_NEW_PASSPHRASE";
+
+/// Environment variable read for the archive passphrase by `backup` and `restore`.
+pub const BACKUP_PASSPHRASE_ENV: &str = "KORALREEF_BACKUP_PASSPHRASE";
+
+/// Where the operator's passphrase comes from. It is only read when the
+/// database is passphrase-protected or a passphrase is being set.
+#[derive(Clone, PartialEq, Eq)]
+pub enum PassphraseSource {
+    /// Read from an environment variable.
+    Env(String),
+    /// Already read, by [`PassphraseSource::take_from_env`].
+    Given(String),
+    /// Read the first line from an already open file descriptor.
+    Fd(i32),
+    /// Ask on the terminal.
+    Prompt,
+}
+
+impl fmt::Debug for PassphraseSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PassphraseSource::Env(var) => f.debug_tuple("Env").field(var).finish(),
+            PassphraseSource::Given(_) => f.write_str("Given(..)"),
+            PassphraseSource::Fd(fd) => f.debug_tuple("Fd").field(fd).finish(),
+            PassphraseSource::Prompt => f.write_str("Prompt"),
+        }
+    }
+}
+
+impl Drop for PassphraseSource {
+    fn drop(&mut self) {
+        if let PassphraseSource::Given(passphrase) = self {
+            passphrase.zeroize();
+        }
+    }
+}
+
+impl PassphraseSource {
+    /// The descriptor when one is given, else `env_var` when it is set, else
+    /// an interactive prompt.
+    pub fn resolve(fd: Option<i32>, env_var: &str) -> Self {
+        match fd {
+            Some(fd) => PassphraseSource::Fd(fd),
+            None if std::env::var_os(env_var).is_some() => PassphraseSource::Env(env_var.to_string()),
+            None => PassphraseSource::Prompt,
+        }
+    }
+
+    /// Like [`resolve`](Self::resolve), but a passphrase in `env_var` is read
+    /// now and the variable removed, so child processes do not inherit it.
+    /// Removing a variable races with other threads reading the environment,
+    /// so this must run before any are started.
+    pub fn take_from_env(fd: Option<i32>, env_var: &str) -> Self {
+        let source = Self::resolve(fd, env_var);
+        let PassphraseSource::Env(ref var) = source else {
+            return source;
+        };
+        match std::env::var(var) {
+            Ok(passphrase) => {
+                std::env::remove_var(var);
+                PassphraseSource::Given(passphrase)
+            }
+            // Not valid UTF-8; `read` reports it.
+            Err(_) => source,
+        }
+    }
+
+    pub fn read(&self, prompt: &str) -> Result<String> {
+        let passphrase = match self {
+            PassphraseSource::Env(var) => std::env::var(var).with_context(|| format!("{} is not set", var))?,
+            PassphraseSource::Given(passphrase) => passphrase.clone(),
+            PassphraseSource::Fd(fd) => read_fd(*fd)?,
+            PassphraseSource::Prompt => rpassword::prompt_password(prompt)
+                .context("Failed to read passphrase from the terminal")?,
+        };
+        if passphrase.is_empty() {
+            bail!("Passphrase must not be empty");
+        }
+        Ok(passphrase)
+    }
+
+    /// Reads a new passphrase, asking twice when prompting.
+    pub fn read_new(&self) -> Result<String> {
+        let passphrase = self.read("New passphrase: ")?;
+        if *self == PassphraseSource::Prompt {
+            let mut confirm = self.read("Repeat passphrase: ")?;
+            let matches = confirm == passphrase;
+            confirm.zeroize();
+            if !matches {
+                bail!("Passphrases do not match");
+            }
+        }
+        Ok(passphrase)
+    }
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<String> {
+    use std::os::unix::io::FromRawFd;
+    // SAFETY: the operator handed us this descriptor for exactly this purpose;
+    // it is consumed and closed here.
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    let mut buf = String::new();
+    file.read_to_string(&mut buf).with_context(|| format!("Failed to read passphrase from fd {}", fd))?;
+    let passphrase = buf.lines().next().unwrap_or_default().to_string();
+    buf.zeroize();
+    Ok(passphrase)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Result<String> {
+    bail!("Reading the passphrase from a file descriptor is only supported on Unix")
+}
+
+/// Argon2id parameters and salt for a passphrase-derived master key, stored
+/// in the `master_key_kdf` setting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    /// Base64 salt.
+    pub salt: String,
+    /// A known value encrypted under the derived key, so a wrong passphrase
+    /// is reported as such instead of as a decryption failure later on.
+    pub check: String,
+}
+
+impl KdfParams {
+    /// Fresh parameters with a random salt; `check` is filled in by the caller.
+    pub fn generate() -> Self {
+        let mut salt = [0u8; 16];
+        thread_rng().fill_bytes(&mut salt);
+        Self {
+            algorithm: "argon2id".to_string(),
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+            salt: base64::Engine::encode(&base64::prelude::BASE64_STANDARD, salt),
+            check: String::new(),
+        }
+    }
+
+    pub fn derive_key(&self, passphrase: &str) -> Result<[u8; 32]> {
+        if self.algorithm != "argon2id" {
+            bail!("Unsupported key derivation algorithm '{}'", self.algorithm);
+        }
+        let salt = base64::Engine::decode(&base64::prelude::BASE64_STANDARD, &self.salt)?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+}
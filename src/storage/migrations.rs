@@ -0,0 +1,174 @@
+//! Ordered schema migrations for `koral.db`.
+//!
+//! Each migration runs in its own transaction together with the version bump,
+//! so a failure leaves the database at the previous version. Databases created
+//! before versioning have no `schema_version` table and start at version 0;
+//! the early migrations use `IF NOT EXISTS` so such databases, whatever tables
+//! they already have, are adopted without losing data.
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+
+/// `(version, description, sql)`, in the order they are applied. Never edit a
+/// released migration; add a new one instead.
+const MIGRATIONS: &[(u32, &str, &str)] = &[
+    (1, "settings, users and history", "
+        CREATE TABLE IF NOT EXISTS settings (
+            id INTEGER PRIMARY KEY,
+            key TEXT UNIQUE,
+            value TEXT,
+            is_encrypted INTEGER DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY,
+            telegram_id INTEGER UNIQUE,
+            is_admin INTEGER DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS history (
+            id INTEGER PRIMARY KEY,
+            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+            event TEXT
+        );
+    "),
+    (2, "simulations, scan reports and whitelist rules", "
+        CREATE TABLE IF NOT EXISTS simulations (
+            id INTEGER PRIMARY KEY,
+            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+            accounts TEXT,
+            units_consumed INTEGER,
+            error TEXT,
+            logs TEXT
+        );
+        CREATE TABLE IF NOT EXISTS scan_reports (
+            id INTEGER PRIMARY KEY,
+            scanned_at INTEGER,
+            total INTEGER,
+            reclaimable INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS scan_entries (
+            id INTEGER PRIMARY KEY,
+            report_id INTEGER REFERENCES scan_reports(id),
+            pubkey TEXT,
+            mint TEXT,
+            lamports INTEGER,
+            status TEXT
+        );
+        CREATE TABLE IF NOT EXISTS account_sightings (
+            pubkey TEXT PRIMARY KEY,
+            first_seen INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS empty_accounts (
+            pubkey TEXT PRIMARY KEY,
+            empty_since INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS whitelist_rules (
+            id INTEGER PRIMARY KEY,
+            kind TEXT,
+            value TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(kind, value)
+        );
+    "),
+    (3, "reclaim journal, reclamations and lifetime stats", "
+        CREATE TABLE IF NOT EXISTS reclaim_journal (
+            signature TEXT PRIMARY KEY,
+            blockhash TEXT,
+            accounts TEXT,
+            lamports INTEGER,
+            fee_lamports INTEGER,
+            status TEXT,
+            slot INTEGER,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS reclamations (
+            id INTEGER PRIMARY KEY,
+            pubkey TEXT,
+            mint TEXT,
+            lamports INTEGER,
+            signature TEXT,
+            slot INTEGER,
+            fee_lamports INTEGER,
+            treasury TEXT,
+            reclaimed_at INTEGER,
+            UNIQUE(pubkey, signature)
+        );
+        CREATE TABLE IF NOT EXISTS app_stats (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            reclaimed_lamports INTEGER NOT NULL DEFAULT 0,
+            accounts_closed INTEGER NOT NULL DEFAULT 0,
+            last_scan_at INTEGER
+        );
+    "),
+    (4, "versioned master keys", "
+        CREATE TABLE master_keys (
+            version INTEGER PRIMARY KEY,
+            wrapped TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+    "),
+    // `is_admin` is kept for older builds; `role` is authoritative from here on.
+    (5, "user roles", "
+        ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer';
+        UPDATE users SET role = 'owner' WHERE is_admin = 1;
+    "),
+    (6, "sentinel pause", "
+        CREATE TABLE sentinel_pause (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            paused_at INTEGER NOT NULL,
+            paused_until INTEGER,
+            paused_by TEXT NOT NULL
+        );
+    "),
+    // Scans only return the operator's own accounts, so an owner rule either
+    // matched every account or none.
+    (7, "retire owner rules", "
+        DELETE FROM whitelist_rules WHERE kind = 'owner';
+    "),
+];
+
+/// The schema version this build writes and expects.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].0;
+
+/// The version recorded in `conn`, 0 for a database that predates versioning.
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    let has_table: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Ok(0);
+    }
+    let version: Option<u32> = conn
+        .query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+        .optional()?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Brings `conn` up to [`SCHEMA_VERSION`], returning the version it started
+/// at. Refuses databases written by a newer build rather than risk
+/// corrupting them.
+pub fn migrate(conn: &mut Connection) -> Result<u32> {
+    let initial = schema_version(conn)?;
+    if initial > SCHEMA_VERSION {
+        bail!(
+            "Database schema version {} is newer than this build supports ({}); upgrade koralreef",
+            initial, SCHEMA_VERSION
+        );
+    }
+
+    for (version, description, sql) in MIGRATIONS.iter().filter(|(v, _, _)| *v > initial) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);
+             DELETE FROM schema_version;",
+        )?;
+        tx.execute_batch(sql)
+            .with_context(|| format!("Migration {} ({}) failed", version, description))?;
+        tx.execute("INSERT INTO schema_version (version) VALUES (?1)", [version])?;
+        tx.commit()?;
+        log::info!("Migrated database to schema version {} ({})", version, description);
+    }
+    Ok(initial)
+}
@@ -0,0 +1,83 @@
+// Bot logic that does not need Telegram: permissions and confirmations.
+
+use koralreef::bot::commands::{Command, WhitelistAction};
+use koralreef::bot::confirm::{ConfirmError, Confirmations, PendingAction, CONFIRM_TTL_SECS};
+use koralreef::core::safety::Rule;
+use koralreef::storage::Role;
+use solana_sdk::pubkey::Pubkey;
+
+#[test]
+fn destructive_commands_need_higher_roles() {
+    assert_eq!(Command::Stats.required_role(), Role::Viewer);
+    assert_eq!(Command::Log.required_role(), Role::Viewer);
+    assert_eq!(Command::Sweep.required_role(), Role::Operator);
+    assert_eq!(Command::Pause("2h".into()).required_role(), Role::Operator);
+    assert_eq!(Command::Resume.required_role(), Role::Operator);
+    assert_eq!(Command::Mode.required_role(), Role::Owner);
+    assert_eq!(Command::Grant("1 viewer".into()).required_role(), Role::Owner);
+    assert_eq!(Command::Whitelist("list".into()).required_role(), Role::Viewer);
+    assert_eq!(Command::Whitelist("add mint x".into()).required_role(), Role::Owner);
+    assert_eq!(PendingAction::Sweep.required_role(), Command::Sweep.required_role());
+    assert_eq!(PendingAction::EnableReal.required_role(), Command::Mode.required_role());
+}
+
+#[test]
+fn confirmations_are_single_use_and_bound_to_their_user() {
+    let mut confirmations = Confirmations::default();
+    let now = 1_700_000_000;
+    let nonce = confirmations.issue(PendingAction::Sweep, 1, now);
+    let other = confirmations.issue(PendingAction::Sweep, 1, now);
+    assert_ne!(nonce, other);
+
+    // Another user's tap does not use up the confirmation.
+    assert_eq!(confirmations.take(&nonce, 2, now), Err(ConfirmError::WrongUser));
+    assert_eq!(confirmations.take(&nonce, 1, now + 1), Ok(PendingAction::Sweep));
+    assert_eq!(confirmations.take(&nonce, 1, now + 1), Err(ConfirmError::Unknown));
+    assert_eq!(confirmations.take("deadbeef", 1, now), Err(ConfirmError::Unknown));
+}
+
+#[test]
+fn confirmations_expire() {
+    let mut confirmations = Confirmations::default();
+    let now = 1_700_000_000;
+    let nonce = confirmations.issue(PendingAction::EnableReal, 1, now);
+    assert_eq!(confirmations.take(&nonce, 1, now + CONFIRM_TTL_SECS), Err(ConfirmError::Expired));
+    assert_eq!(confirmations.take(&nonce, 1, now), Err(ConfirmError::Unknown));
+
+    // Issuing prunes stale entries, so they are gone even if never tapped.
+    let stale = confirmations.issue(PendingAction::Sweep, 1, now);
+    confirmations.issue(PendingAction::Sweep, 1, now + CONFIRM_TTL_SECS);
+    assert_eq!(confirmations.take(&stale, 1, now), Err(ConfirmError::Unknown));
+}
+
+#[test]
+fn whitelist_arguments_are_validated_as_pubkeys() {
+    let key = Pubkey::new_unique();
+    assert_eq!(WhitelistAction::parse("").unwrap(), WhitelistAction::List);
+    assert_eq!(WhitelistAction::parse(" list ").unwrap(), WhitelistAction::List);
+    assert_eq!(WhitelistAction::parse(&format!("add {}", key)).unwrap(), WhitelistAction::Add(Rule::ProtectAccount(key)));
+    assert_eq!(WhitelistAction::parse(&format!("add mint {}", key)).unwrap(), WhitelistAction::Add(Rule::ProtectMint(key)));
+    assert_eq!(WhitelistAction::parse(&format!("remove account {}", key)).unwrap(), WhitelistAction::Remove(Rule::ProtectAccount(key)));
+
+    let err = WhitelistAction::parse("add not-a-pubkey").unwrap_err();
+    assert!(err.to_string().contains("Invalid pubkey"), "{}", err);
+    // Only whitelist kinds; other rules stay on the command line.
+    assert!(WhitelistAction::parse(&format!("add owner {}", key)).is_err());
+    assert!(WhitelistAction::parse("drop everything").is_err());
+}
+
+#[test]
+fn guests_in_demo_mode_cannot_pause_or_resume() {
+    assert!(Command::Sweep.open_in_demo());
+    assert!(!Command::Stats.open_in_demo());
+    // A pause is persisted and outlives Demo mode, so it needs a registered operator.
+    assert!(!Command::Pause("".into()).open_in_demo());
+    assert!(!Command::Pause("1d".into()).open_in_demo());
+    assert!(!Command::Resume.open_in_demo());
+    // A preview scans the real operator's accounts.
+    assert!(!Command::Preview.open_in_demo());
+    assert!(!Command::Report.open_in_demo());
+    assert!(!Command::Mode.open_in_demo());
+    assert!(!Command::Grant("1 owner".into()).open_in_demo());
+    assert!(!Command::Whitelist("add mint x".into()).open_in_demo());
+}
@@ -1,5 +1,4 @@
-// Placeholder for integration tests
-// In a real scenario, this would mock the Solana RPC or use a local test validator
+// Configuration tests. Sweeps against the in-memory ledger live in sweep_test.rs.
 
 #[test]
 fn test_config_load() {
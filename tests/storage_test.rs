@@ -0,0 +1,633 @@
+// Storage behaviour that must hold across restarts and upgrades.
+
+use koralreef::config::AppMode;
+use koralreef::core::safety::{RejectionReason, Rule};
+use koralreef::core::scanner::{ScanEntry, ScanReport};
+use koralreef::state::AppState;
+use koralreef::storage::keys::PassphraseSource;
+use koralreef::storage::{backup, migrations, parse_duration, Pause, Role, Storage, User};
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+
+#[test]
+fn lifetime_stats_survive_reopening_the_database() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_eq!(storage.lifetime_stats().unwrap().last_scan_at, None);
+
+    let mut state = AppState::new(AppMode::Real);
+    let stats = storage.record_scan(1_700_000_000, 4_000_000, 2).unwrap();
+    state.record_scan(4_000_000, 2, Some(stats));
+    let stats = storage.record_scan(1_700_003_600, 1_000_000, 1).unwrap();
+    state.record_scan(1_000_000, 1, Some(stats));
+    assert_eq!(state.total_accounts_closed, 3);
+    assert_eq!(state.lifetime_accounts_closed, 3);
+    drop(storage);
+
+    // After a restart only the lifetime totals and last scan come back.
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    let mut restarted = AppState::new(AppMode::Real);
+    restarted.restore(storage.lifetime_stats().unwrap());
+    assert_eq!(restarted.lifetime_reclaimed_lamports, 5_000_000);
+    assert_eq!(restarted.lifetime_accounts_closed, 3);
+    assert_eq!(restarted.last_scan_time, Some(1_700_003_600));
+    assert_eq!(restarted.total_reclaimed_lamports, 0);
+    assert_eq!(restarted.total_accounts_closed, 0);
+}
+
+/// Tables as the first release created them, before schema versioning.
+const LEGACY_SCHEMA: &str = "
+    CREATE TABLE settings (id INTEGER PRIMARY KEY, key TEXT UNIQUE, value TEXT, is_encrypted INTEGER DEFAULT 0);
+    CREATE TABLE users (id INTEGER PRIMARY KEY, telegram_id INTEGER UNIQUE, is_admin INTEGER DEFAULT 0);
+    CREATE TABLE history (id INTEGER PRIMARY KEY, timestamp DATETIME DEFAULT CURRENT_TIMESTAMP, event TEXT);
+    INSERT INTO users (telegram_id, is_admin) VALUES (42, 1);
+    INSERT INTO history (event) VALUES ('legacy event');
+";
+
+/// Tables added by schema version 2.
+const V2_TABLES: &str = "
+    CREATE TABLE simulations (id INTEGER PRIMARY KEY, timestamp DATETIME DEFAULT CURRENT_TIMESTAMP, accounts TEXT, units_consumed INTEGER, error TEXT, logs TEXT);
+    CREATE TABLE scan_reports (id INTEGER PRIMARY KEY, scanned_at INTEGER, total INTEGER, reclaimable INTEGER);
+    CREATE TABLE scan_entries (id INTEGER PRIMARY KEY, report_id INTEGER REFERENCES scan_reports(id), pubkey TEXT, mint TEXT, lamports INTEGER, status TEXT);
+    CREATE TABLE account_sightings (pubkey TEXT PRIMARY KEY, first_seen INTEGER);
+    CREATE TABLE empty_accounts (pubkey TEXT PRIMARY KEY, empty_since INTEGER);
+    CREATE TABLE whitelist_rules (id INTEGER PRIMARY KEY, kind TEXT, value TEXT, created_at DATETIME DEFAULT CURRENT_TIMESTAMP, UNIQUE(kind, value));
+    INSERT INTO whitelist_rules (kind, value) VALUES ('min-age-days', '3');
+";
+
+/// Tables added by schema version 3.
+const V3_TABLES: &str = "
+    CREATE TABLE reclaim_journal (signature TEXT PRIMARY KEY, blockhash TEXT, accounts TEXT, lamports INTEGER, fee_lamports INTEGER, status TEXT, slot INTEGER, created_at DATETIME DEFAULT CURRENT_TIMESTAMP, updated_at DATETIME DEFAULT CURRENT_TIMESTAMP);
+    CREATE TABLE reclamations (id INTEGER PRIMARY KEY, pubkey TEXT, mint TEXT, lamports INTEGER, signature TEXT, slot INTEGER, fee_lamports INTEGER, treasury TEXT, reclaimed_at INTEGER, UNIQUE(pubkey, signature));
+    CREATE TABLE app_stats (id INTEGER PRIMARY KEY CHECK (id = 1), reclaimed_lamports INTEGER NOT NULL DEFAULT 0, accounts_closed INTEGER NOT NULL DEFAULT 0, last_scan_at INTEGER);
+";
+
+/// Table added by schema version 4.
+const V4_TABLES: &str = "
+    CREATE TABLE master_keys (version INTEGER PRIMARY KEY, wrapped TEXT NOT NULL, created_at DATETIME DEFAULT CURRENT_TIMESTAMP);
+";
+
+/// Role column added by schema version 5.
+const V5_CHANGES: &str = "
+    ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer';
+    UPDATE users SET role = 'owner' WHERE is_admin = 1;
+    INSERT INTO users (telegram_id, role) VALUES (43, 'operator');
+";
+
+/// Table added by schema version 6.
+const V6_TABLES: &str = "
+    CREATE TABLE sentinel_pause (id INTEGER PRIMARY KEY CHECK (id = 1), paused_at INTEGER NOT NULL, paused_until INTEGER, paused_by TEXT NOT NULL);
+    INSERT INTO sentinel_pause (id, paused_at, paused_until, paused_by) VALUES (1, 1700000000, NULL, 'cli');
+";
+
+/// Writes a `koral.db` fixture into a fresh directory.
+fn fixture(sql: &str, version: Option<u32>) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let conn = rusqlite::Connection::open(dir.path().join("koral.db")).unwrap();
+    conn.execute_batch(sql).unwrap();
+    if let Some(version) = version {
+        conn.execute_batch("CREATE TABLE schema_version (version INTEGER NOT NULL)").unwrap();
+        conn.execute("INSERT INTO schema_version (version) VALUES (?1)", [version]).unwrap();
+    }
+    dir
+}
+
+fn assert_fully_migrated(storage: &Storage) {
+    assert_eq!(storage.schema_version().unwrap(), migrations::SCHEMA_VERSION);
+    assert_eq!(storage.user_role(42).unwrap(), Some(Role::Owner));
+    assert!(storage.get_recent_history(10).unwrap()[0].ends_with("legacy event"));
+    assert_eq!(storage.lifetime_stats().unwrap(), Default::default());
+    assert!(storage.in_flight_batches().unwrap().is_empty());
+}
+
+#[test]
+fn fresh_database_is_created_at_the_current_version() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_eq!(storage.schema_version().unwrap(), migrations::SCHEMA_VERSION);
+    assert!(storage.list_rules().unwrap().is_empty());
+}
+
+#[test]
+fn unversioned_database_is_migrated() {
+    let dir = fixture(LEGACY_SCHEMA, None);
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+}
+
+#[test]
+fn version_1_database_is_migrated() {
+    let dir = fixture(LEGACY_SCHEMA, Some(1));
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert!(storage.list_rules().unwrap().is_empty());
+}
+
+#[test]
+fn version_2_database_is_migrated() {
+    let dir = fixture(&format!("{}{}", LEGACY_SCHEMA, V2_TABLES), Some(2));
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert_eq!(storage.list_rules().unwrap(), vec![Rule::MinAgeDays(3)]);
+}
+
+#[test]
+fn version_3_database_is_migrated() {
+    let dir = fixture(&format!("{}{}{}", LEGACY_SCHEMA, V2_TABLES, V3_TABLES), Some(3));
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert_eq!(storage.rotate_key(None).unwrap().0, 1);
+}
+
+#[test]
+fn version_4_database_is_migrated() {
+    let dir = fixture(&format!("{}{}{}{}", LEGACY_SCHEMA, V2_TABLES, V3_TABLES, V4_TABLES), Some(4));
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert_eq!(storage.key_version(), 0);
+}
+
+#[test]
+fn version_5_database_is_migrated() {
+    let dir = fixture(&format!("{}{}{}{}{}", LEGACY_SCHEMA, V2_TABLES, V3_TABLES, V4_TABLES, V5_CHANGES), Some(5));
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert_eq!(storage.user_role(43).unwrap(), Some(Role::Operator));
+}
+
+#[test]
+fn version_6_database_is_migrated() {
+    // Owner rules were retired by version 7; one left behind would fail to parse.
+    let owner_rule = "INSERT INTO whitelist_rules (kind, value) VALUES ('owner', '11111111111111111111111111111111');";
+    let dir = fixture(&format!("{}{}{}{}{}{}{}", LEGACY_SCHEMA, V2_TABLES, V3_TABLES, V4_TABLES, V5_CHANGES, V6_TABLES, owner_rule), Some(6));
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert_eq!(storage.pause().unwrap(), Some(Pause { paused_at: 1_700_000_000, until: None, by: "cli".into() }));
+    assert_eq!(storage.list_rules().unwrap(), vec![Rule::MinAgeDays(3)]);
+}
+
+#[test]
+fn unversioned_database_with_later_tables_is_migrated() {
+    // Installs that ran a build between versioning and this one already have
+    // some of the later tables but no version table.
+    let dir = fixture(&format!("{}{}", LEGACY_SCHEMA, V2_TABLES), None);
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_fully_migrated(&storage);
+    assert_eq!(storage.list_rules().unwrap(), vec![Rule::MinAgeDays(3)]);
+}
+
+#[test]
+fn newer_database_is_refused() {
+    let dir = fixture(LEGACY_SCHEMA, Some(migrations::SCHEMA_VERSION + 1));
+    let err = Storage::open(dir.path().to_path_buf()).err().unwrap();
+    assert!(format!("{:#}", err).contains("newer than this build supports"));
+
+    // The database is left untouched.
+    let conn = rusqlite::Connection::open(dir.path().join("koral.db")).unwrap();
+    assert_eq!(migrations::schema_version(&conn).unwrap(), migrations::SCHEMA_VERSION + 1);
+}
+
+#[test]
+fn plaintext_key_install_migrates_to_a_passphrase() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.save_keypair("[1,2,3]").unwrap();
+    storage.set_setting("bot_token", "123:abc", true).unwrap();
+    assert!(dir.path().join(".key").exists());
+
+    storage.set_passphrase("correct horse").unwrap();
+    assert!(!dir.path().join(".key").exists());
+    assert!(storage.is_passphrase_protected().unwrap());
+    assert_eq!(storage.get_keypair().unwrap().as_deref(), Some("[1,2,3]"));
+    drop(storage);
+
+    let storage = Storage::open_with(dir.path().to_path_buf(), &PassphraseSource::Given("correct horse".into())).unwrap();
+    assert_eq!(storage.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+    assert!(!dir.path().join(".key").exists());
+
+    let err = Storage::open_with(dir.path().to_path_buf(), &PassphraseSource::Given("battery staple".into())).err().unwrap();
+    assert_eq!(err.to_string(), "Wrong passphrase");
+}
+
+#[test]
+fn rotation_replaces_the_root_key_and_retires_old_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.set_setting("bot_token", "123:abc", true).unwrap();
+    // Sealed under the key that is about to be retired.
+    let stale = storage.encrypt("solana_keypair", b"[9,9,9]").unwrap();
+    let old_root = std::fs::read(dir.path().join(".key")).unwrap();
+
+    assert_eq!(storage.rotate_key(None).unwrap(), (1, 1));
+    assert_eq!(storage.rotate_key(None).unwrap(), (2, 1));
+    assert_eq!(storage.key_version(), 2);
+    let new_root = std::fs::read(dir.path().join(".key")).unwrap();
+    assert_ne!(new_root, old_root);
+    assert!(!dir.path().join(".key.new").exists());
+    let conn = rusqlite::Connection::open(dir.path().join("koral.db")).unwrap();
+    let token: String = conn.query_row("SELECT value FROM settings WHERE key = 'bot_token'", [], |r| r.get(0)).unwrap();
+    assert!(token.starts_with("v2.k2:"));
+    let versions: u32 = conn.query_row("SELECT COUNT(*) FROM master_keys", [], |r| r.get(0)).unwrap();
+    assert_eq!(versions, 1);
+
+    // Nothing sealed under a superseded key can be read any more.
+    conn.execute("INSERT INTO settings (key, value, is_encrypted) VALUES ('solana_keypair', ?1, 1)", [&stale]).unwrap();
+    assert!(storage.get_keypair().is_err());
+    conn.execute("DELETE FROM settings WHERE key = 'solana_keypair'", []).unwrap();
+    drop(storage);
+
+    // Nor does a copy of the old key file open the database.
+    std::fs::write(dir.path().join(".key"), &old_root).unwrap();
+    assert!(Storage::open(dir.path().to_path_buf()).is_err());
+    std::fs::write(dir.path().join(".key"), &new_root).unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_eq!(storage.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+
+    // A passphrase-derived root key is re-derived under a new salt.
+    storage.set_passphrase("rotate me").unwrap();
+    let salt = |conn: &rusqlite::Connection| -> String {
+        conn.query_row("SELECT value FROM settings WHERE key = 'master_key_kdf'", [], |r| r.get(0)).unwrap()
+    };
+    let before = salt(&conn);
+    let err = storage.rotate_key(None).unwrap_err();
+    assert!(err.to_string().contains("passphrase is needed"), "{}", err);
+    assert_eq!(storage.rotate_key(Some("wrong")).unwrap_err().to_string(), "Wrong passphrase");
+    assert_eq!(storage.rotate_key(Some("rotate me")).unwrap(), (3, 1));
+    assert_ne!(salt(&conn), before);
+    drop(storage);
+    let storage = Storage::open_with(dir.path().to_path_buf(), &PassphraseSource::Given("rotate me".into())).unwrap();
+    assert_eq!(storage.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+}
+
+#[test]
+fn interrupted_rotation_is_finished_or_discarded_on_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.set_setting("bot_token", "123:abc", true).unwrap();
+
+    // Stopped before committing: the new key file is discarded.
+    std::fs::write(dir.path().join(".key.new"), [3u8; 32]).unwrap();
+    let reopened = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert!(!dir.path().join(".key.new").exists());
+    assert_eq!(reopened.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+
+    // Stopped after committing but before the rename: the rename is finished.
+    let old_root = std::fs::read(dir.path().join(".key")).unwrap();
+    storage.rotate_key(None).unwrap();
+    std::fs::rename(dir.path().join(".key"), dir.path().join(".key.new")).unwrap();
+    std::fs::write(dir.path().join(".key"), &old_root).unwrap();
+    let reopened = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert!(!dir.path().join(".key.new").exists());
+    assert_eq!(reopened.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+}
+
+#[test]
+fn swapped_ciphertexts_are_reported_as_tampering() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.set_setting("bot_token", "123:abc", true).unwrap();
+    storage.save_keypair("[1,2,3]").unwrap();
+
+    let conn = rusqlite::Connection::open(dir.path().join("koral.db")).unwrap();
+    conn.execute(
+        "UPDATE settings SET value = (SELECT value FROM settings WHERE key = 'bot_token') WHERE key = 'solana_keypair'",
+        [],
+    ).unwrap();
+
+    let err = storage.get_keypair().unwrap_err();
+    assert!(err.to_string().contains("'solana_keypair' failed its integrity check"), "{}", err);
+    assert_eq!(storage.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+}
+
+#[test]
+fn unbound_legacy_ciphertexts_are_upgraded_on_open() {
+    use aes_gcm::aead::{Aead, KeyInit};
+    use aes_gcm::{Aes256Gcm, Nonce};
+
+    // An install from before values were bound: a key file and an unbound
+    // value, base64(nonce || ciphertext) with no tag or associated data.
+    let dir = tempfile::tempdir().unwrap();
+    let key = [5u8; 32];
+    std::fs::write(dir.path().join(".key"), key).unwrap();
+
+    let nonce = [7u8; 12];
+    let mut combined = nonce.to_vec();
+    combined.extend(Aes256Gcm::new(&key.into()).encrypt(&Nonce::from(nonce), b"123:abc".as_slice()).unwrap());
+    let legacy = base64::Engine::encode(&base64::prelude::BASE64_STANDARD, combined);
+    let conn = rusqlite::Connection::open(dir.path().join("koral.db")).unwrap();
+    conn.execute_batch(LEGACY_SCHEMA).unwrap();
+    conn.execute("INSERT INTO settings (key, value, is_encrypted) VALUES ('bot_token', ?1, 1)", [&legacy]).unwrap();
+
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_eq!(storage.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+    let stored: String = conn.query_row("SELECT value FROM settings WHERE key = 'bot_token'", [], |r| r.get(0)).unwrap();
+    assert!(stored.starts_with("v2.k0:"));
+
+    // Once upgraded, an unbound value showing up again is not trusted, even
+    // after reopening.
+    conn.execute("UPDATE settings SET value = ?1 WHERE key = 'bot_token'", [&legacy]).unwrap();
+    let err = storage.get_setting("bot_token").unwrap_err();
+    assert!(err.to_string().contains("tampered"), "{}", err);
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert!(storage.get_setting("bot_token").is_err());
+}
+
+#[test]
+fn backup_restores_into_a_new_home() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.save_keypair("[1,2,3]").unwrap();
+    storage.set_setting("bot_token", "123:abc", true).unwrap();
+    let archive = dir.path().join("koral.bak");
+    let summary = storage.backup(&archive, "archive pass").unwrap();
+    assert_eq!(summary.files, vec!["koral.db".to_string(), ".key".to_string()]);
+    assert!(!dir.path().join(".backup-snapshot.db").exists());
+
+    let home = tempfile::tempdir().unwrap();
+    let target = home.path().join(".koralReef");
+    let err = backup::restore(&archive, "wrong pass", &target, false).unwrap_err();
+    assert!(err.to_string().contains("integrity check"), "{}", err);
+    assert!(!target.join("koral.db").exists());
+
+    backup::restore(&archive, "archive pass", &target, false).unwrap();
+    let restored = Storage::open(target.clone()).unwrap();
+    assert_eq!(restored.get_keypair().unwrap().as_deref(), Some("[1,2,3]"));
+    assert_eq!(restored.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+
+    // An existing database is only replaced when forced.
+    restored.set_setting("bot_token", "456:def", true).unwrap();
+    let err = backup::restore(&archive, "archive pass", &target, false).unwrap_err();
+    assert!(err.to_string().contains("--force"), "{}", err);
+    assert_eq!(restored.get_setting("bot_token").unwrap().as_deref(), Some("456:def"));
+    backup::restore(&archive, "archive pass", &target, true).unwrap();
+    assert_eq!(restored.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+}
+
+#[test]
+fn tampered_or_foreign_backups_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    let archive = dir.path().join("koral.bak");
+    storage.backup(&archive, "archive pass").unwrap();
+    let target = tempfile::tempdir().unwrap();
+
+    let mut bytes = std::fs::read(&archive).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    std::fs::write(&archive, &bytes).unwrap();
+    let err = backup::restore(&archive, "archive pass", target.path(), false).unwrap_err();
+    assert!(err.to_string().contains("integrity check"), "{}", err);
+
+    // The version header is covered too: bumping it is reported, not ignored.
+    bytes[last] ^= 1;
+    bytes[8] = 99;
+    std::fs::write(&archive, &bytes).unwrap();
+    let err = backup::restore(&archive, "archive pass", target.path(), false).unwrap_err();
+    assert!(err.to_string().contains("Unsupported backup version"), "{}", err);
+
+    std::fs::write(&archive, b"not a backup").unwrap();
+    let err = backup::restore(&archive, "archive pass", target.path(), false).unwrap_err();
+    assert!(err.to_string().contains("Not a koralreef backup"), "{}", err);
+    assert!(!target.path().join("koral.db").exists());
+}
+
+#[test]
+fn passphrase_protected_backup_restores_without_a_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.save_keypair("[4,5,6]").unwrap();
+    storage.set_passphrase("db pass").unwrap();
+    let archive = dir.path().join("koral.bak");
+    assert_eq!(storage.backup(&archive, "archive pass").unwrap().files, vec!["koral.db".to_string()]);
+
+    // A stale key file in the target would shadow the passphrase; it is removed.
+    let target = tempfile::tempdir().unwrap();
+    std::fs::write(target.path().join(".key"), [1u8; 32]).unwrap();
+    backup::restore(&archive, "archive pass", target.path(), false).unwrap();
+    assert!(!target.path().join(".key").exists());
+
+    let restored = Storage::open_with(target.path().to_path_buf(), &PassphraseSource::Given("db pass".into())).unwrap();
+    assert_eq!(restored.get_keypair().unwrap().as_deref(), Some("[4,5,6]"));
+}
+
+#[test]
+fn failed_restore_keeps_the_old_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.set_passphrase("db pass").unwrap();
+    let archive = dir.path().join("koral.bak");
+    storage.backup(&archive, "archive pass").unwrap();
+
+    // A directory in the database's place makes the rename fail.
+    let target = tempfile::tempdir().unwrap();
+    std::fs::write(target.path().join(".key"), [1u8; 32]).unwrap();
+    std::fs::create_dir(target.path().join("koral.db")).unwrap();
+    std::fs::write(target.path().join("koral.db").join("keep"), b"").unwrap();
+    assert!(backup::restore(&archive, "archive pass", target.path(), true).is_err());
+    assert_eq!(std::fs::read(target.path().join(".key")).unwrap(), [1u8; 32]);
+    assert!(!target.path().join("koral.db.restore").exists());
+}
+
+#[test]
+fn restore_is_refused_while_a_daemon_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.save_keypair("[1,2,3]").unwrap();
+    let archive = dir.path().join("koral.bak");
+    storage.backup(&archive, "archive pass").unwrap();
+
+    let target = tempfile::tempdir().unwrap();
+    let daemon = Storage::open(target.path().to_path_buf()).unwrap();
+    let lock = daemon.lock_daemon().unwrap();
+    let err = backup::restore(&archive, "archive pass", target.path(), true).unwrap_err();
+    assert!(err.to_string().contains("daemon is running"), "{}", err);
+    assert_eq!(daemon.get_keypair().unwrap(), None);
+
+    drop(lock);
+    backup::restore(&archive, "archive pass", target.path(), true).unwrap();
+    let restored = Storage::open(target.path().to_path_buf()).unwrap();
+    assert_eq!(restored.get_keypair().unwrap().as_deref(), Some("[1,2,3]"));
+}
+
+fn scan_entry(pubkey: Pubkey, lamports: u64, verdict: Result<(), RejectionReason>) -> ScanEntry {
+    ScanEntry {
+        pubkey,
+        account: Account { lamports, ..Default::default() },
+        mint: Some(Pubkey::new_unique()),
+        amount: Some(0),
+        verdict,
+    }
+}
+
+#[test]
+fn scan_reports_read_back_with_reasons_and_ages() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    let (old, young, funded) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+    let first = 1_700_000_000;
+    let later = first + 2 * 86_400 + 3 * 3_600;
+    let too_new = || Err(RejectionReason::TooNew { age_secs: later as u64 - first as u64, min_age_secs: 7 * 86_400 });
+    storage.save_scan_report(&ScanReport {
+        scanned_at: first,
+        entries: vec![scan_entry(old, 2_000_000, Ok(())), scan_entry(young, 2_000_000, too_new())],
+    }).unwrap();
+    storage.save_scan_report(&ScanReport {
+        scanned_at: later,
+        entries: vec![
+            scan_entry(old, 2_000_000, Ok(())),
+            scan_entry(young, 3_000_000, too_new()),
+            scan_entry(funded, 1_000_000, Err(RejectionReason::NonZeroBalance(5))),
+        ],
+    }).unwrap();
+
+    let report = storage.latest_scan_report().unwrap().unwrap();
+    assert_eq!((report.total, report.reclaimable), (3, 1));
+    let rows: Vec<(String, u64, &str)> = report.entries.iter()
+        .map(|row| (row.pubkey.clone(), row.age_secs, row.status.as_str()))
+        .collect();
+    assert_eq!(rows, vec![
+        (old.to_string(), later as u64 - first as u64, "reclaimable"),
+        (young.to_string(), later as u64 - first as u64, "first seen 51h ago, protected for 168h"),
+        (funded.to_string(), 0, "token balance is 5"),
+    ]);
+
+    let text = report.render(10);
+    assert!(text.starts_with("Scan at 2023-11-17 01:13:20 UTC: 3 accounts, 1 reclaimable"), "{}", text);
+    assert!(text.contains("0.002000 SOL, age 2d 3h\n  reclaimable"), "{}", text);
+    assert!(text.contains("0.003000 SOL, age 2d 3h\n  first seen 51h ago, protected for 168h"), "{}", text);
+    assert!(text.contains("0.001000 SOL, age 0m\n  token balance is 5"), "{}", text);
+}
+
+#[test]
+fn only_the_latest_scan_reports_are_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    let gone = Pubkey::new_unique();
+    let kept = Pubkey::new_unique();
+    storage.save_scan_report(&ScanReport { scanned_at: 1_000, entries: vec![scan_entry(gone, 1, Ok(()))] }).unwrap();
+    for i in 1..15 {
+        storage.save_scan_report(&ScanReport { scanned_at: 1_000 + i, entries: vec![scan_entry(kept, 2, Ok(()))] }).unwrap();
+    }
+
+    let conn = rusqlite::Connection::open(dir.path().join("koral.db")).unwrap();
+    let count = |table: &str| conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get::<_, u32>(0)).unwrap();
+    assert_eq!((count("scan_reports"), count("scan_entries")), (10, 10));
+    assert_eq!(storage.last_scanned(&gone).unwrap(), None);
+    assert_eq!(storage.last_scanned(&kept).unwrap().map(|(_, lamports)| lamports), Some(2));
+    // Ages still count from the first sighting, which outlives its report.
+    assert_eq!(storage.first_seen().unwrap()[&kept], 1_001);
+    assert_eq!(storage.latest_scan_report().unwrap().unwrap().entries[0].age_secs, 13);
+}
+
+#[test]
+fn configured_users_are_seeded_as_owners_and_keep_later_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_eq!(storage.seed_users(&[1, 2]).unwrap(), 2);
+    assert!(!storage.claim_first_owner(99).unwrap());
+    assert_eq!(storage.user_role(99).unwrap(), None);
+
+    storage.set_user_role(2, Role::Viewer).unwrap();
+    storage.set_user_role(3, Role::Operator).unwrap();
+    // Seeding again on the next start does not undo the demotion.
+    assert_eq!(storage.seed_users(&[1, 2]).unwrap(), 0);
+    assert_eq!(storage.users().unwrap(), vec![
+        User { telegram_id: 1, role: Role::Owner },
+        User { telegram_id: 3, role: Role::Operator },
+        User { telegram_id: 2, role: Role::Viewer },
+    ]);
+    assert_eq!(storage.owners().unwrap(), vec![1]);
+    assert!(Role::Owner > Role::Operator && Role::Operator > Role::Viewer);
+}
+
+#[test]
+fn the_last_owner_cannot_be_removed_or_demoted() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert!(storage.claim_first_owner(7).unwrap());
+    assert!(!storage.claim_first_owner(8).unwrap());
+
+    let err = storage.revoke_user(7).unwrap_err();
+    assert!(err.to_string().contains("last owner"), "{}", err);
+    assert!(storage.set_user_role(7, Role::Operator).is_err());
+    assert_eq!(storage.user_role(7).unwrap(), Some(Role::Owner));
+
+    storage.set_user_role(8, Role::Owner).unwrap();
+    assert!(storage.revoke_user(7).unwrap());
+    assert!(!storage.revoke_user(7).unwrap());
+    assert_eq!(storage.owners().unwrap(), vec![8]);
+    assert!(Role::parse("admin").is_err());
+}
+
+#[test]
+fn pause_survives_reopening_until_resumed() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    assert_eq!(storage.pause().unwrap(), None);
+    assert!(!storage.resume().unwrap());
+
+    let now = 1_700_000_000;
+    let pause = Pause { paused_at: now, until: Some(now + 7200), by: "telegram:7".into() };
+    storage.set_pause(&pause).unwrap();
+    drop(storage);
+
+    let storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    let stored = storage.pause().unwrap().unwrap();
+    assert_eq!(stored, pause);
+    assert_eq!(stored.describe(now), "paused for another 2h");
+    assert!(!stored.has_ended(now + 7199));
+    assert!(stored.has_ended(now + 7200));
+
+    // A later pause replaces the earlier one.
+    let indefinite = Pause { paused_at: now + 60, until: None, by: "cli".into() };
+    storage.set_pause(&indefinite).unwrap();
+    assert_eq!(storage.pause().unwrap(), Some(indefinite.clone()));
+    assert!(!indefinite.has_ended(i64::MAX));
+    assert_eq!(indefinite.describe(now), "paused until resumed");
+
+    assert!(storage.resume().unwrap());
+    assert_eq!(storage.pause().unwrap(), None);
+}
+
+#[test]
+fn pause_durations_are_parsed() {
+    assert_eq!(parse_duration("90s").unwrap(), 90);
+    assert_eq!(parse_duration("30m").unwrap(), 1800);
+    assert_eq!(parse_duration("2h").unwrap(), 7200);
+    assert_eq!(parse_duration(" 1d ").unwrap(), 86400);
+    assert_eq!(parse_duration("1h30m").unwrap(), 5400);
+    for invalid in ["", "0m", "30", "h", "2 hours", "-1h", "99999999999999999999d"] {
+        assert!(parse_duration(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn given_passphrases_can_be_read_again_but_not_printed() {
+    // Tests run on many threads, so they never change the environment.
+    let source = PassphraseSource::Given("from env".into());
+    assert_eq!(source.read("").unwrap(), "from env");
+    assert_eq!(source.read("").unwrap(), "from env");
+    assert_eq!(format!("{:?}", source), "Given(..)");
+    assert_eq!(PassphraseSource::take_from_env(Some(3), "KORALREEF_TEST_UNSET_PASSPHRASE"), PassphraseSource::Fd(3));
+    assert_eq!(PassphraseSource::take_from_env(None, "KORALREEF_TEST_UNSET_PASSPHRASE"), PassphraseSource::Prompt);
+}
+
+#[test]
+fn key_changes_are_refused_while_a_daemon_runs() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut storage = Storage::open(dir.path().to_path_buf()).unwrap();
+    storage.set_setting("bot_token", "123:abc", true).unwrap();
+    let daemon = Storage::open(dir.path().to_path_buf()).unwrap();
+    let lock = daemon.lock_daemon().unwrap();
+    assert!(daemon.lock_daemon().is_err());
+
+    let err = storage.rotate_key(None).unwrap_err();
+    assert!(err.to_string().contains("daemon is running"), "{}", err);
+    assert!(storage.set_passphrase("too soon").is_err());
+    assert_eq!(storage.key_version(), 0);
+    assert!(!storage.is_passphrase_protected().unwrap());
+
+    drop(lock);
+    assert_eq!(storage.rotate_key(None).unwrap(), (1, 1));
+    assert_eq!(storage.get_setting("bot_token").unwrap().as_deref(), Some("123:abc"));
+}
@@ -0,0 +1,695 @@
+// End-to-end sweeps against the in-memory ledger; no network required.
+
+use std::sync::Arc;
+use koralreef::config::{Config, Settings};
+use koralreef::core::fees::{self, FeePolicy, LAMPORTS_PER_SIGNATURE};
+use koralreef::core::ledger::memory::{TokenAccountOptions, TokenExtension};
+use koralreef::core::ledger::InMemoryLedger;
+use koralreef::core::reclaimer::{BatchDisposition, Reclaimer};
+use koralreef::core::safety::{self, Policy, RejectionReason, Rule};
+use koralreef::core::scanner::{ScanReport, Scanner};
+use koralreef::core::sweep::{preview_sweep, run_sweep, sweep_policy};
+use koralreef::core::ledger::LedgerClient;
+use koralreef::storage::Storage;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use tokio_util::sync::CancellationToken;
+
+const RENT: u64 = 2_039_280;
+
+struct Harness {
+    ledger: Arc<InMemoryLedger>,
+    scanner: Scanner,
+    reclaimer: Reclaimer,
+    storage: Arc<Storage>,
+    operator: Pubkey,
+    /// Same key as the reclaimer's, for building transactions by hand.
+    signer: Keypair,
+    treasury: Pubkey,
+    _dir: tempfile::TempDir,
+}
+
+fn harness() -> Harness {
+    harness_with(FeePolicy::default())
+}
+
+fn harness_with(fee_policy: FeePolicy) -> Harness {
+    let ledger = Arc::new(InMemoryLedger::new());
+    let keypair = Keypair::new();
+    let operator = keypair.pubkey();
+    let signer = keypair.insecure_clone();
+    let treasury = Pubkey::new_unique();
+    ledger.set_balance(&operator, 1_000_000_000);
+
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(Storage::open(dir.path().to_path_buf()).unwrap());
+    Harness {
+        scanner: Scanner::with_client(ledger.clone()),
+        reclaimer: Reclaimer::with_client(ledger.clone(), keypair, treasury)
+            .with_fee_policy(fee_policy)
+            .with_journal(storage.clone()),
+        storage,
+        ledger,
+        operator,
+        signer,
+        treasury,
+        _dir: dir,
+    }
+}
+
+fn settings(dry_run: bool) -> Settings {
+    let mut settings = Config::demo().settings;
+    settings.dry_run = dry_run;
+    settings
+}
+
+#[tokio::test]
+async fn sweep_closes_empty_accounts_of_both_token_programs() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let legacy = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    let token_2022 = h.ledger.add_token_account(&spl_token_2022::id(), &h.operator, &mint, 0, RENT);
+    let funded = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 5, RENT);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+
+    assert_eq!(result.report.entries.len(), 3);
+    assert_eq!(result.outcome.closed_count(), 2);
+    assert_eq!(result.outcome.total_lamports(), 2 * RENT);
+    assert!(h.ledger.account(&legacy).is_none());
+    assert!(h.ledger.account(&token_2022).is_none());
+    assert!(h.ledger.account(&funded).is_some());
+    assert_eq!(h.ledger.balance(&h.treasury), 2 * RENT);
+    assert!(h.storage.in_flight_batches().unwrap().is_empty());
+
+    let stored = h.storage.latest_scan_report().unwrap().unwrap();
+    assert_eq!((stored.total, stored.reclaimable), (3, 2));
+}
+
+#[tokio::test]
+async fn token_2022_accounts_with_extensions_are_found_and_closed() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let extended = h.ledger.add_token_account_with(&spl_token_2022::id(), &h.operator, &mint, 0, RENT, TokenAccountOptions {
+        extensions: vec![TokenExtension::TransferFeeAmount(0), TokenExtension::ConfidentialTransfer { has_balance: false }],
+        ..Default::default()
+    });
+    // Longer than a legacy account, so a size filter would have hidden it.
+    assert!(h.ledger.account(&extended).unwrap().data.len() > 165);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.report.entries.len(), 1);
+    assert_eq!(result.outcome.closed_count(), 1);
+    assert!(h.ledger.account(&extended).is_none());
+}
+
+#[tokio::test]
+async fn extensions_still_holding_value_block_the_close() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let with_extension = |ext| h.ledger.add_token_account_with(&spl_token_2022::id(), &h.operator, &mint, 0, RENT, TokenAccountOptions {
+        extensions: vec![ext],
+        ..Default::default()
+    });
+    let withheld = with_extension(TokenExtension::TransferFeeAmount(5));
+    let confidential = with_extension(TokenExtension::ConfidentialTransfer { has_balance: true });
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.closed_count(), 0);
+    assert_eq!(h.ledger.transaction_count(), 0);
+    for (account, extension) in [(withheld, "TransferFeeAmount"), (confidential, "ConfidentialTransferAccount")] {
+        assert!(h.ledger.account(&account).is_some());
+        let entry = result.report.entries.iter().find(|e| e.pubkey == account).unwrap();
+        assert_eq!(entry.verdict, Err(RejectionReason::ExtensionNotClosable(extension)));
+    }
+}
+
+/// Scans as a sweep at `now` would and saves the report, which is what moves
+/// the grace period timers.
+async fn scan_at(h: &Harness, settings: &Settings, now: i64) -> ScanReport {
+    let mut policy = sweep_policy(h.operator, &h.storage, settings).unwrap();
+    policy.now = now;
+    let report = h.scanner.scan(&policy).await.unwrap();
+    h.storage.save_scan_report(&report).unwrap();
+    report
+}
+
+fn set_token_amount(ledger: &InMemoryLedger, account: &Pubkey, amount: u64) {
+    let mut state = ledger.account(account).unwrap();
+    state.data[64..72].copy_from_slice(&amount.to_le_bytes());
+    ledger.set_account(*account, state);
+}
+
+#[tokio::test]
+async fn emptied_accounts_wait_out_the_grace_period() {
+    let h = harness();
+    let mut settings = settings(false);
+    settings.grace_period_minutes = Some(30);
+    let account = h.ledger.add_token_account(&spl_token::id(), &h.operator, &Pubkey::new_unique(), 0, RENT);
+
+    // Just emptied: kept open for the whole period.
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings, &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.report.entries[0].verdict, Err(RejectionReason::GracePeriod { remaining_secs: 1_800 }));
+    assert_eq!(result.outcome.closed_count(), 0);
+    assert!(h.ledger.account(&account).is_some());
+    let emptied_at = h.storage.empty_since().unwrap()[&account];
+    assert_eq!(emptied_at, result.report.scanned_at);
+
+    let report = scan_at(&h, &settings, emptied_at + 1_200).await;
+    assert_eq!(report.entries[0].verdict, Err(RejectionReason::GracePeriod { remaining_secs: 600 }));
+
+    // Once the period has passed it is closed.
+    let report = scan_at(&h, &settings, emptied_at + 1_800).await;
+    assert!(report.entries[0].is_reclaimable());
+    let outcome = h.reclaimer.reclaim_accounts(&report.reclaimable(), false, &CancellationToken::new()).await.unwrap();
+    assert_eq!(outcome.closed_count(), 1);
+    assert!(h.ledger.account(&account).is_none());
+}
+
+#[tokio::test]
+async fn a_refill_restarts_the_grace_period() {
+    let h = harness();
+    let mut settings = settings(false);
+    settings.grace_period_minutes = Some(30);
+    let account = h.ledger.add_token_account(&spl_token::id(), &h.operator, &Pubkey::new_unique(), 0, RENT);
+    let start = 1_700_000_000;
+    scan_at(&h, &settings, start).await;
+
+    set_token_amount(&h.ledger, &account, 5);
+    let report = scan_at(&h, &settings, start + 600).await;
+    assert_eq!(report.entries[0].verdict, Err(RejectionReason::NonZeroBalance(5)));
+    assert!(h.storage.empty_since().unwrap().is_empty());
+
+    // Emptied again: the timer counts from this scan, not the first.
+    set_token_amount(&h.ledger, &account, 0);
+    scan_at(&h, &settings, start + 1_200).await;
+    assert_eq!(h.storage.empty_since().unwrap()[&account], start + 1_200);
+    let report = scan_at(&h, &settings, start + 1_800).await;
+    assert_eq!(report.entries[0].verdict, Err(RejectionReason::GracePeriod { remaining_secs: 1_200 }));
+    let report = scan_at(&h, &settings, start + 3_000).await;
+    assert!(report.entries[0].is_reclaimable());
+}
+
+/// Sweeps a lone empty account created with `options` and returns why it
+/// was kept, after checking it is still open and the report says the same.
+async fn sweep_rejection(options: TokenAccountOptions) -> RejectionReason {
+    let h = harness();
+    let account = h.ledger.add_token_account_with(&spl_token::id(), &h.operator, &Pubkey::new_unique(), 0, RENT, options);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.closed_count(), 0);
+    assert_eq!(h.ledger.transaction_count(), 0);
+    assert!(h.ledger.account(&account).is_some());
+    let reason = result.report.entries[0].verdict.clone().unwrap_err();
+    let stored = h.storage.latest_scan_report().unwrap().unwrap();
+    assert_eq!(stored.entries[0].status, reason.to_string());
+    reason
+}
+
+#[tokio::test]
+async fn frozen_accounts_are_kept() {
+    let reason = sweep_rejection(TokenAccountOptions { frozen: true, ..Default::default() }).await;
+    assert_eq!(reason, RejectionReason::Frozen);
+}
+
+#[tokio::test]
+async fn native_accounts_are_kept() {
+    let reason = sweep_rejection(TokenAccountOptions { native: Some(RENT), ..Default::default() }).await;
+    assert_eq!(reason, RejectionReason::NativeAccount);
+}
+
+#[tokio::test]
+async fn delegated_accounts_are_kept() {
+    let delegate = Pubkey::new_unique();
+    let reason = sweep_rejection(TokenAccountOptions { delegate: Some(delegate), ..Default::default() }).await;
+    assert_eq!(reason, RejectionReason::ActiveDelegate(delegate));
+}
+
+#[tokio::test]
+async fn accounts_with_a_foreign_close_authority_are_kept() {
+    let authority = Pubkey::new_unique();
+    let reason = sweep_rejection(TokenAccountOptions { close_authority: Some(authority), ..Default::default() }).await;
+    assert_eq!(reason, RejectionReason::CloseAuthorityMismatch(authority));
+
+    // The operator as close authority is as good as ownership.
+    let h = harness();
+    let account = h.ledger.add_token_account_with(&spl_token::id(), &h.operator, &Pubkey::new_unique(), 0, RENT, TokenAccountOptions {
+        close_authority: Some(h.operator),
+        ..Default::default()
+    });
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert!(h.ledger.account(&account).is_none());
+}
+
+#[tokio::test]
+async fn accounts_of_other_owners_are_kept() {
+    let h = harness();
+    let owner = Pubkey::new_unique();
+    let account = h.ledger.add_token_account(&spl_token::id(), &owner, &Pubkey::new_unique(), 0, RENT);
+
+    // The scan never returns it, and the safety check refuses it if handed it anyway.
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert!(result.report.entries.is_empty());
+    let policy = Policy::new(h.operator, &[]);
+    let reason = safety::check_reclaimable(&account, &h.ledger.account(&account).unwrap(), &policy).unwrap_err();
+    assert_eq!(reason, RejectionReason::NotOwner(owner));
+    assert!(h.ledger.account(&account).is_some());
+}
+
+#[tokio::test]
+async fn token_2022_mints_matched_by_the_owner_filter_are_skipped() {
+    let ledger = Arc::new(InMemoryLedger::new());
+    let mint = ledger.add_token_2022_mint(&Pubkey::new_unique(), RENT);
+    // The bytes the owner filter compares; whoever they spell gets the mint back.
+    let owner = Pubkey::try_from(&ledger.account(&mint).unwrap().data[32..64]).unwrap();
+    let account = ledger.add_token_account(&spl_token_2022::id(), &owner, &mint, 0, RENT);
+    assert_eq!(ledger.get_token_accounts(&spl_token_2022::id(), &owner).await.unwrap().len(), 2);
+
+    let report = Scanner::with_client(ledger.clone()).scan(&Policy::new(owner, &[])).await.unwrap();
+    let scanned: Vec<Pubkey> = report.entries.iter().map(|e| e.pubkey).collect();
+    assert_eq!(scanned, vec![account]);
+    assert!(report.entries[0].is_reclaimable());
+}
+
+#[tokio::test]
+async fn sweep_respects_stored_rules_and_dry_run() {
+    let h = harness();
+    let protected = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+    let kept = h.ledger.add_token_account(&spl_token::id(), &h.operator, &protected, 0, RENT);
+    let closable = h.ledger.add_token_account(&spl_token::id(), &h.operator, &other, 0, RENT);
+    h.storage.add_rule(&Rule::ProtectMint(protected)).unwrap();
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(true), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.closed_count(), 1);
+    assert_eq!(result.outcome.simulations.len(), 1);
+    assert!(result.outcome.simulations[0].error.is_none());
+    assert_eq!(h.ledger.transaction_count(), 0);
+    assert!(h.ledger.account(&closable).is_some());
+
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert!(h.ledger.account(&closable).is_none());
+    assert!(h.ledger.account(&kept).is_some());
+}
+
+#[tokio::test]
+async fn explicit_protections_win_over_sweep_mint() {
+    let h = harness();
+    let swept = Pubkey::new_unique();
+    let contested = Pubkey::new_unique();
+    let young = h.ledger.add_token_account(&spl_token::id(), &h.operator, &swept, 0, RENT);
+    let listed = h.ledger.add_token_account(&spl_token::id(), &h.operator, &swept, 0, RENT);
+    let protected = h.ledger.add_token_account(&spl_token::id(), &h.operator, &contested, 0, RENT);
+    let other = h.ledger.add_token_account(&spl_token::id(), &h.operator, &Pubkey::new_unique(), 0, RENT);
+    for rule in [Rule::MinAgeDays(7), Rule::SweepMint(swept), Rule::SweepMint(contested), Rule::ProtectMint(contested)] {
+        h.storage.add_rule(&rule).unwrap();
+    }
+    let mut settings = settings(false);
+    settings.whitelist = vec![listed.to_string()];
+
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings, &CancellationToken::new()).await.unwrap();
+    // sweep-mint only overrides the age rule.
+    assert!(h.ledger.account(&young).is_none());
+    assert!(h.ledger.account(&other).is_some());
+    assert!(h.ledger.account(&listed).is_some());
+    assert!(h.ledger.account(&protected).is_some());
+}
+
+#[tokio::test]
+async fn failing_account_is_dropped_from_its_batch() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let accounts: Vec<Pubkey> = (0..3)
+        .map(|_| h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT))
+        .collect();
+
+    let policy = Policy::new(h.operator, &[]);
+    let candidates = h.scanner.find_reclaimable_accounts(&policy).await.unwrap();
+    assert_eq!(candidates.len(), 3);
+
+    // The middle account is refilled between the scan and the close.
+    let refilled = candidates[1].0;
+    let mut account = h.ledger.account(&refilled).unwrap();
+    account.data[64..72].copy_from_slice(&7u64.to_le_bytes());
+    h.ledger.set_account(refilled, account);
+
+    let outcome = h.reclaimer.reclaim_accounts(&candidates, false, &CancellationToken::new()).await.unwrap();
+    assert_eq!(outcome.closed_count(), 2);
+    assert_eq!(outcome.failed.len(), 1);
+    assert_eq!(outcome.failed[0].pubkey, refilled);
+    assert!(accounts.iter().filter(|p| **p != refilled).all(|p| h.ledger.account(p).is_none()));
+    assert_eq!(h.ledger.transaction_count(), 1);
+}
+
+#[tokio::test]
+async fn batches_are_sent_concurrently_and_all_land() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    for _ in 0..95 {
+        h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    }
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.batches.len(), 5);
+    assert_eq!(result.outcome.closed_count(), 95);
+    assert_eq!(h.ledger.transaction_count(), 5);
+    assert_eq!(result.outcome.treasury_delta, Some(95 * RENT as i64));
+    assert!(!result.outcome.cancelled);
+}
+
+#[tokio::test]
+async fn rpc_failure_partway_keeps_the_batches_that_landed() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    for _ in 0..45 {
+        h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    }
+    h.ledger.fail_blockhash_after(1);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(h.ledger.transaction_count(), 1);
+    assert_eq!(result.outcome.closed_count(), 20);
+    assert_eq!(result.outcome.errors.len(), 2);
+    assert!(result.outcome.errors[0].contains("RPC node unreachable"), "{:?}", result.outcome.errors);
+    assert!(result.summary().contains("2 batches abandoned"), "{}", result.summary());
+
+    // The landed batch is in the ledger even though the sweep hit errors.
+    assert_eq!(h.storage.reclamations_by_day().unwrap()[0].accounts, 20);
+    assert!(h.storage.in_flight_batches().unwrap().is_empty());
+    assert!(h.storage.get_recent_history(50).unwrap().iter().any(|e| e.contains("Batch abandoned")));
+}
+
+#[tokio::test]
+async fn cancelled_sweep_sends_nothing() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let account = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+
+    let policy = Policy::new(h.operator, &[]);
+    let candidates = h.scanner.find_reclaimable_accounts(&policy).await.unwrap();
+    let outcome = h.reclaimer.reclaim_accounts(&candidates, false, &cancel).await.unwrap();
+    assert!(outcome.cancelled);
+    assert_eq!(outcome.closed_count(), 0);
+    assert_eq!(h.ledger.transaction_count(), 0);
+    assert!(h.ledger.account(&account).is_some());
+
+    assert!(run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &cancel).await.is_err());
+}
+
+#[tokio::test]
+async fn history_records_what_became_of_each_batch() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let account = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(true), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.decisions[0].disposition, BatchDisposition::DryRun);
+
+    // An operator who cannot pay the fee fails simulation; nothing is sent.
+    h.ledger.set_balance(&h.operator, 0);
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.decisions[0].disposition, BatchDisposition::SimulationFailed);
+    assert!(h.ledger.account(&account).is_some());
+
+    h.ledger.set_balance(&h.operator, 1_000_000_000);
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.decisions[0].disposition, BatchDisposition::Sent);
+
+    let history = h.storage.get_recent_history(50).unwrap();
+    let decisions: Vec<&String> = history.iter().filter(|e| e.contains("💰")).collect();
+    assert_eq!(decisions.len(), 3);
+    for disposition in ["→ dry run", "→ simulation failed", "→ sent"] {
+        assert_eq!(decisions.iter().filter(|e| e.ends_with(disposition)).count(), 1, "{:?}", decisions);
+    }
+}
+
+#[test]
+fn percentile_is_nearest_rank() {
+    let recent: Vec<u64> = (1..=10).map(|i| i * 100).collect();
+    assert_eq!(fees::percentile(recent.clone(), 50), 500);
+    assert_eq!(fees::percentile(recent.clone(), 75), 800);
+    assert_eq!(fees::percentile(recent.clone(), 0), 100);
+    assert_eq!(fees::percentile(recent, 100), 1_000);
+    assert_eq!(fees::percentile(Vec::new(), 75), 0);
+}
+
+#[tokio::test]
+async fn auto_priority_fee_prices_batches_at_the_configured_percentile() {
+    let h = harness_with(FeePolicy {
+        compute_unit_limit: Some(40_000),
+        auto_priority_fee: true,
+        priority_fee_percentile: 75,
+        ..Default::default()
+    });
+    h.ledger.set_prioritization_fees((1..=10).map(|i| i * 100).collect());
+    let mint = Pubkey::new_unique();
+    h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    let before = h.ledger.balance(&h.operator);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    // The 75th percentile of the ten recent fees is 800 micro-lamports per CU.
+    let fee = LAMPORTS_PER_SIGNATURE + fees::priority_fee(40_000, 800);
+    assert_eq!(result.outcome.batches[0].fee_lamports, fee);
+    // The ledger charged that fee, so it read both the limit and the price.
+    assert_eq!(before - h.ledger.balance(&h.operator), fee);
+    let budget_program = format!("Program {} invoke", solana_sdk::compute_budget::id());
+    let logs = &result.outcome.simulations[0].logs;
+    assert_eq!(logs.iter().filter(|l| l.starts_with(&budget_program)).count(), 2, "{:?}", logs);
+}
+
+#[tokio::test]
+async fn priority_fee_never_exceeds_the_rent_a_batch_recovers() {
+    let h = harness_with(FeePolicy {
+        compute_unit_price: Some(10_000_000),
+        ..Default::default()
+    });
+    let mint = Pubkey::new_unique();
+    let small_rent = 12_000;
+    let account = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, small_rent);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    // At the configured price this batch would cost 100,000 lamports in priority fees.
+    assert!(fees::priority_fee(fees::DEFAULT_UNITS_PER_CLOSE, 10_000_000) > small_rent);
+    let decision = &result.outcome.decisions[0];
+    assert_eq!(decision.disposition, BatchDisposition::Sent);
+    assert!(decision.fee_lamports < small_rent, "{:?}", decision);
+    assert!(decision.fee_lamports > LAMPORTS_PER_SIGNATURE);
+    assert!(h.ledger.account(&account).is_none());
+}
+
+#[tokio::test]
+async fn per_sweep_priority_fee_cap_holds_across_concurrent_batches() {
+    let cap = 3_000;
+    let h = harness_with(FeePolicy {
+        compute_unit_price: Some(5_000),
+        max_priority_fee_per_sweep: Some(cap),
+        ..Default::default()
+    });
+    let mint = Pubkey::new_unique();
+    for _ in 0..80 {
+        h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    }
+    let before = h.ledger.balance(&h.operator);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    // Four batches of 20, each worth 1,000 lamports of priority fee uncapped.
+    assert_eq!(result.outcome.batches.len(), 4);
+    assert_eq!(result.outcome.closed_count(), 80);
+    let priority: u64 = result.outcome.batches.iter().map(|b| b.fee_lamports - LAMPORTS_PER_SIGNATURE).sum();
+    assert!(priority <= cap, "{} > {}", priority, cap);
+    assert!(priority > 0);
+    assert!(before - h.ledger.balance(&h.operator) <= 4 * LAMPORTS_PER_SIGNATURE + cap);
+}
+
+#[tokio::test]
+async fn unprofitable_batches_are_deferred_and_logged() {
+    let h = harness_with(FeePolicy {
+        min_net_profit: RENT,
+        ..Default::default()
+    });
+    let mint = Pubkey::new_unique();
+    // First dust worth less than the signature fee, then a batch that pays its
+    // fee but falls short of the minimum profit.
+    let dust = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, 4_000);
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    let rent = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    let second = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+
+    for result in [&result, &second] {
+        assert!(result.outcome.decisions.iter().all(|d| d.disposition == BatchDisposition::Deferred));
+        assert!(result.outcome.batches.is_empty() && result.outcome.simulations.is_empty());
+    }
+    assert_eq!(second.outcome.decisions[0].lamports, RENT + 4_000);
+    assert_eq!(h.ledger.transaction_count(), 0);
+    assert!(h.ledger.account(&dust).is_some() && h.ledger.account(&rent).is_some());
+    assert!(h.storage.in_flight_batches().unwrap().is_empty());
+    assert!(h.storage.reclamations_by_day().unwrap().is_empty());
+    assert_eq!(h.storage.lifetime_stats().unwrap().accounts_closed, 0);
+
+    let history = h.storage.get_recent_history(50).unwrap();
+    let deferred: Vec<&String> = history.iter().filter(|e| e.contains("💰") && e.ends_with("→ deferred")).collect();
+    assert_eq!(deferred.len(), 2, "{:?}", history);
+    assert!(deferred.iter().any(|e| e.contains("4000 lamports rent, 5000 lamports fees, net -1000")), "{:?}", deferred);
+}
+
+/// Journals and optionally broadcasts a close of `account`, as if the process
+/// died right after.
+async fn interrupted_batch(h: &Harness, account: Pubkey, broadcast: bool) -> solana_sdk::signature::Signature {
+    let blockhash = h.ledger.get_latest_blockhash().await.unwrap();
+    let ix = spl_token::instruction::close_account(&spl_token::id(), &account, &h.treasury, &h.operator, &[]).unwrap();
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&h.operator), &[&h.signer], blockhash);
+    let signature = tx.signatures[0];
+    h.storage.journal_batch(&signature, &blockhash, &[account], RENT, 5_000).unwrap();
+    if broadcast {
+        h.ledger.send_transaction(&tx).await.unwrap();
+    }
+    signature
+}
+
+#[tokio::test]
+async fn in_flight_batches_are_reconciled_before_new_work() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let landed = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    let lost = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    // The crashed run had scanned both accounts before closing them.
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(true), &CancellationToken::new()).await.unwrap();
+    let landed_sig = interrupted_batch(&h, landed, true).await;
+    interrupted_batch(&h, lost, false).await;
+    h.ledger.expire_blockhash();
+    assert_eq!(h.storage.in_flight_batches().unwrap().len(), 2);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+
+    assert_eq!(result.recovered.len(), 1);
+    assert_eq!(result.recovered[0].signature, landed_sig);
+    assert!(result.recovered[0].slot.is_some());
+    // The lost batch's account is still empty and open, so this sweep closes it.
+    assert_eq!(result.outcome.closed_count(), 1);
+    assert!(h.ledger.account(&lost).is_none());
+    assert_eq!(result.closed_count(), 2);
+    assert!(h.storage.in_flight_batches().unwrap().is_empty());
+    // Both the recovered and the new close are in the ledger, with the
+    // recovered account's details taken from the scan that found it.
+    let by_mint = h.storage.reclamations_by_mint().unwrap();
+    assert_eq!((by_mint[0].key.clone(), by_mint[0].accounts), (mint.to_string(), 2));
+}
+
+#[tokio::test]
+async fn batch_on_a_dropped_fork_is_not_counted() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let account = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    h.ledger.hold_confirmations();
+    // The close lands at `processed` only, then its fork is dropped.
+    let ledger = h.ledger.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        ledger.drop_held();
+    });
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.outcome.closed_count(), 0);
+    assert_eq!(result.outcome.decisions[0].disposition, BatchDisposition::Failed);
+    assert!(h.ledger.account(&account).is_some());
+    assert!(h.storage.in_flight_batches().unwrap().is_empty());
+    assert!(h.storage.reclamations_by_day().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reconciliation_waits_for_processed_batches_to_confirm_or_drop() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let dropped = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(true), &CancellationToken::new()).await.unwrap();
+    h.ledger.hold_confirmations();
+    interrupted_batch(&h, dropped, true).await;
+    let ledger = h.ledger.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        ledger.drop_held();
+    });
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    // Not recovered; the account came back with the fork and is closed anew.
+    assert!(result.recovered.is_empty());
+    assert_eq!(result.outcome.closed_count(), 1);
+    assert!(h.ledger.account(&dropped).is_none());
+    assert_eq!(h.storage.reclamations_by_day().unwrap()[0].accounts, 1);
+
+    // A processed batch that goes on to confirm is recovered.
+    let confirmed = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(true), &CancellationToken::new()).await.unwrap();
+    h.ledger.hold_confirmations();
+    interrupted_batch(&h, confirmed, true).await;
+    let ledger = h.ledger.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        ledger.confirm_held();
+    });
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+    assert_eq!(result.recovered.len(), 1);
+    assert_eq!(result.closed_count(), 1);
+}
+
+#[tokio::test]
+async fn closed_accounts_are_recorded_in_the_reclamations_ledger() {
+    let h = harness();
+    let mint_a = Pubkey::new_unique();
+    let mint_b = Pubkey::new_unique();
+    for _ in 0..3 {
+        h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint_a, 0, RENT);
+    }
+    h.ledger.add_token_account(&spl_token_2022::id(), &h.operator, &mint_b, 0, RENT + 1);
+
+    let result = run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(false), &CancellationToken::new()).await.unwrap();
+
+    let by_mint = h.storage.reclamations_by_mint().unwrap();
+    assert_eq!(by_mint.len(), 2);
+    assert_eq!((by_mint[0].key.as_str(), by_mint[0].accounts, by_mint[0].lamports), (mint_a.to_string().as_str(), 3, 3 * RENT));
+    assert_eq!((by_mint[1].accounts, by_mint[1].lamports), (1, RENT + 1));
+
+    let by_treasury = h.storage.reclamations_by_treasury().unwrap();
+    assert_eq!(by_treasury.len(), 1);
+    assert_eq!(by_treasury[0].key, h.treasury.to_string());
+    assert_eq!(by_treasury[0].fee_lamports, result.outcome.total_fees());
+    assert_eq!(by_treasury[0].net_lamports(), (4 * RENT + 1) as i64 - result.outcome.total_fees() as i64);
+
+    let by_day = h.storage.reclamations_by_day().unwrap();
+    assert_eq!(by_day.len(), 1);
+    assert_eq!(by_day[0].accounts, 4);
+
+    // Dry runs close nothing and record nothing.
+    h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint_a, 0, RENT);
+    run_sweep(&h.scanner, &h.reclaimer, &h.storage, &settings(true), &CancellationToken::new()).await.unwrap();
+    assert_eq!(h.storage.reclamations_by_day().unwrap()[0].accounts, 4);
+}
+
+#[tokio::test]
+async fn preview_lists_candidates_without_closing_or_recording() {
+    let h = harness();
+    let mint = Pubkey::new_unique();
+    let protected = Pubkey::new_unique();
+    let small = h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 0, RENT);
+    let large = h.ledger.add_token_account(&spl_token_2022::id(), &h.operator, &mint, 0, 2 * RENT);
+    h.ledger.add_token_account(&spl_token::id(), &h.operator, &protected, 0, RENT);
+    h.ledger.add_token_account(&spl_token::id(), &h.operator, &mint, 5, RENT);
+    h.storage.add_rule(&Rule::ProtectMint(protected)).unwrap();
+
+    let preview = preview_sweep(&h.scanner, h.operator, &h.storage, &settings(false)).await.unwrap();
+    let candidates: Vec<Pubkey> = preview.candidates.iter().map(|c| c.pubkey).collect();
+    assert_eq!(candidates, vec![large, small]);
+    assert_eq!(preview.candidates[0].mint, Some(mint));
+    assert_eq!(preview.total_lamports(), 3 * RENT);
+
+    let text = preview.render(1);
+    assert!(text.starts_with("🔎 Preview: 2 accounts, 0.006118 SOL reclaimable"), "{}", text);
+    assert!(text.contains(&large.to_string()) && !text.contains(&small.to_string()));
+    assert!(text.contains("… and 1 more"));
+
+    assert_eq!(h.ledger.transaction_count(), 0);
+    assert!(h.storage.latest_scan_report().unwrap().is_none());
+}